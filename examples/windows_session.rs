@@ -0,0 +1,30 @@
+extern crate clipboard;
+
+#[cfg(windows)]
+fn main() {
+    use clipboard::ClipboardProvider;
+    use clipboard::windows_clipboard::WindowsClipboardContext;
+    use std::time::Instant;
+
+    const ROUNDS: u32 = 1000;
+    let mut ctx: WindowsClipboardContext = ClipboardProvider::new().unwrap();
+    ctx.set_contents("Hello, world!".to_owned()).unwrap();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        ctx.get_target_contents("13").unwrap();
+    }
+    println!("per call: {:?} per read", start.elapsed() / ROUNDS);
+
+    let session = ctx.session().unwrap();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        session.get("13").unwrap();
+    }
+    println!("session:  {:?} per read", start.elapsed() / ROUNDS);
+}
+
+#[cfg(not(windows))]
+fn main() {
+    println!("Clipboard sessions are only available under windows!");
+}
@@ -0,0 +1,132 @@
+/*
+Copyright 2016 Avraham Weinstock
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use common::ClipboardProvider;
+use std::error::Error;
+use std::time::Duration;
+use ClipboardContext;
+
+/// Configure every tunable of the platform's `ClipboardContext` in one place.
+///
+/// Settings that don't apply to the current platform are ignored, and
+/// anything left unset keeps the context's default.
+///
+/// Some knobs are deliberately missing. The waiting methods take their poll
+/// interval as an argument, the X11 selection is the type parameter of
+/// `X11ClipboardContext` rather than a preference, there is no Wayland
+/// backend with seats, and the backend is chosen at compile time, with the
+/// `nop` feature as the only override.
+#[derive(Clone, Debug, Default)]
+pub struct ClipboardContextBuilder {
+    read_timeout: Option<Duration>,
+    x11_property: Option<String>,
+    windows_retries: Option<(u32, Duration)>,
+    windows_fail_fast: bool,
+    max_read_bytes: Option<usize>,
+    image_preference: Option<&'static [&'static str]>,
+}
+
+impl ClipboardContextBuilder {
+    /// Start from the defaults of `ClipboardContext::new`
+    pub fn new() -> ClipboardContextBuilder {
+        ClipboardContextBuilder::default()
+    }
+
+    /// How long a single read waits for the selection owner (X11 only), unlike
+    /// `DEFAULT_MAX_WAIT` which bounds the waiting methods
+    pub fn read_timeout(mut self, read_timeout: Duration) -> ClipboardContextBuilder {
+        self.read_timeout = Some(read_timeout);
+        self
+    }
+
+    /// Receive transfers through the named property instead of the default
+    /// (X11 only), see `X11ClipboardContext::with_property`
+    pub fn x11_property(mut self, name: &str) -> ClipboardContextBuilder {
+        self.x11_property = Some(name.to_owned());
+        self
+    }
+
+    /// How often opening the clipboard is attempted and the delay in between
+    /// (Windows only)
+    pub fn windows_retries(mut self, attempts: u32, attempt_delay: Duration) -> ClipboardContextBuilder {
+        self.windows_retries = Some((attempts, attempt_delay));
+        self
+    }
+
+    /// Make writes give up after the first attempt to open a busy clipboard
+    /// (Windows only), see `WritePolicy::FailFast`
+    pub fn windows_fail_fast(mut self) -> ClipboardContextBuilder {
+        self.windows_fail_fast = true;
+        self
+    }
+
+    /// Largest target read before giving up with an error
+    pub fn max_read_bytes(mut self, max_read_bytes: usize) -> ClipboardContextBuilder {
+        self.max_read_bytes = Some(max_read_bytes);
+        self
+    }
+
+    /// Image targets `get_image_bytes` tries, most preferred first
+    pub fn image_preference(mut self, targets: &'static [&'static str]) -> ClipboardContextBuilder {
+        self.image_preference = Some(targets);
+        self
+    }
+
+    // every platform uses a different subset of the settings
+    #[allow(unused_mut, unused_variables)]
+    pub fn build(self) -> Result<ClipboardContext, Box<dyn Error + Send + Sync>> {
+        let ClipboardContextBuilder {
+            read_timeout,
+            x11_property,
+            windows_retries,
+            windows_fail_fast,
+            max_read_bytes,
+            image_preference,
+        } = self;
+        #[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+        let mut ctx = match x11_property {
+            Some(ref name) => ClipboardContext::with_property(name)?,
+            None => ClipboardContext::new()?,
+        };
+        #[cfg(not(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop"))))]
+        let mut ctx = ClipboardContext::new()?;
+        #[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+        {
+            if let Some(read_timeout) = read_timeout {
+                ctx.set_read_timeout(read_timeout);
+            }
+        }
+        #[cfg(all(windows, not(feature = "nop")))]
+        {
+            if let Some((attempts, attempt_delay)) = windows_retries {
+                ctx.set_retries(attempts, attempt_delay);
+            }
+            if windows_fail_fast {
+                ctx.set_write_policy(::windows_clipboard::WritePolicy::FailFast);
+            }
+        }
+        #[cfg(all(any(unix, windows), not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+        {
+            if let Some(max_read_bytes) = max_read_bytes {
+                ctx.set_max_read_bytes(max_read_bytes);
+            }
+            if let Some(image_preference) = image_preference {
+                ctx.set_image_preference(image_preference);
+            }
+        }
+        Ok(ctx)
+    }
+}
@@ -16,36 +16,576 @@ limitations under the License.
 
 use std::error::Error;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use formats;
+use rich_text;
+#[cfg(feature = "image")]
+use image::{self, Image};
+#[cfg(feature = "hash")]
+use sha2::{Digest, Sha256};
 
-// pub fn err(s: &str) -> Box<dyn Error> {
-//     Box::<dyn Error + Send + Sync>::from(s)
-// }
+/// Largest target a context reads by default before giving up, see the
+/// `set_max_read_bytes` method of each context
+pub const DEFAULT_MAX_READ_BYTES: usize = 256 * 1024 * 1024;
+
+/// How often the waiting methods check the clipboard when no interval is given
+pub const DEFAULT_POLL_DURATION: Duration = Duration::from_millis(50);
+/// How long the waiting methods wait when no timeout is given
+pub const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(3);
+
+/// Targets to set at once along with their data, see `set_multiple_targets`
+pub type TargetMap<'a, T> = HashMap<T, &'a [u8]>;
+/// A target along with the data read for it
+pub type TargetData<T = String> = (T, Vec<u8>);
+
+/// Error for a read aborted because the data exceeds the context's limit
+pub fn too_large(limit: usize) -> Box<dyn Error + Send + Sync> {
+    format!("clipboard data exceeds the read limit of {} bytes", limit).into()
+}
+
+/// How the `get_files_as` method of a context returns files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Local paths; entries that aren't `file://` urls are skipped
+    Paths,
+    /// Percent-encoded `file://` urls
+    Urls,
+}
+
+/// Files read by `get_files_as`, in the requested `FileFormat`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FileList {
+    Paths(Vec<PathBuf>),
+    Urls(Vec<String>),
+}
+
+// escapes everything but unreserved characters and the separators of a path
+#[cfg(any(windows, all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")))))]
+pub(crate) fn percent_encode(bytes: &[u8], url: &mut String) {
+    for &byte in bytes {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => url.push(byte as char),
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+}
+
+// None for a malformed escape
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten"))))]
+pub(crate) fn percent_decode(text: &str) -> Option<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(::std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(decoded)
+}
+
+/// Timer clearing a secret set by `ClipboardProvider::set_contents_expiring`
+pub struct ClearHandle {
+    cancelled: Arc<(Mutex<bool>, Condvar)>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl ClearHandle {
+    // runs `expire` once `ttl` has passed, or drops it when cancelled first
+    pub(crate) fn spawn(ttl: Duration, expire: impl FnOnce() + Send + 'static) -> ClearHandle {
+        let cancelled = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = cancelled.clone();
+        let thread = thread::spawn(move || {
+            let deadline = Instant::now() + ttl;
+            let (lock, condvar) = &*signal;
+            let mut cancelled = lock.lock().unwrap();
+            while !*cancelled {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                cancelled = condvar.wait_timeout(cancelled, deadline - now).unwrap().0;
+            }
+            if !*cancelled {
+                drop(cancelled);
+                expire();
+            }
+        });
+        ClearHandle { cancelled, thread }
+    }
+
+    /// Stop the timer, leaving the clipboard as it is
+    pub fn cancel(self) {
+        let (lock, condvar) = &*self.cancelled;
+        *lock.lock().unwrap() = true;
+        condvar.notify_one();
+        let _ = self.thread.join();
+    }
+
+    /// Block until the timer fired, e.g. to keep a command line tool alive
+    /// until the secret is gone
+    pub fn wait(self) {
+        let _ = self.thread.join();
+    }
+}
+
+/// Reject invalid UTF-8 for text targets before anything reaches the clipboard,
+/// so a bad entry can't leave a multi-target write partially applied
+#[cfg(all(unix, not(any(target_os="android", target_os="emscripten"))))]
+pub fn check_text_encoding(target: &str, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if formats::UTF8_TEXT.contains(&target) && ::std::str::from_utf8(data).is_err() {
+        return Err(format!("invalid text encoding for target {}: expected UTF-8", target).into());
+    }
+    Ok(())
+}
+
+/// Drop a leading byte order mark some Windows applications put in front
+/// of text, which otherwise surfaces as U+FEFF and breaks comparisons
+pub fn strip_bom(text: String) -> String {
+    if text.starts_with('\u{feff}') {
+        text['\u{feff}'.len_utf8()..].to_owned()
+    } else {
+        text
+    }
+}
+
+/// `strip_bom` for the raw data of UTF-8 text targets, other targets are
+/// returned untouched
+#[cfg(all(unix, not(any(target_os="android", target_os="emscripten"))))]
+pub fn strip_text_bom(target: &str, mut data: Vec<u8>) -> Vec<u8> {
+    if formats::UTF8_TEXT.contains(&target) && data.starts_with(b"\xef\xbb\xbf") {
+        data.drain(..3);
+    }
+    data
+}
+
+// text as held by formats::TEXT; CF_UNICODETEXT holds NUL-terminated UTF-16
+fn text_target_data(text: &str) -> Vec<u8> {
+    #[cfg(windows)]
+    return text.encode_utf16().chain(Some(0)).flat_map(|unit| unit.to_le_bytes().to_vec()).collect();
+    #[cfg(not(windows))]
+    return text.as_bytes().to_vec();
+}
+
+// the image under every target set_image writes
+#[cfg(feature = "image")]
+fn encode_image(image: &Image) -> Result<Vec<TargetData<&'static str>>, Box<dyn Error + Send + Sync>> {
+    image::WRITE_TARGETS.iter()
+        .map(|&(target, encoding)| Ok((target, image.encode(encoding)?)))
+        .collect()
+}
+
+#[cfg(target_os="macos")]
+pub fn err(s: &str) -> Box<dyn Error + Send + Sync> {
+    Box::<dyn Error + Send + Sync>::from(s)
+}
 
 /// Trait for clipboard access
 pub trait ClipboardProvider: Sized {
     /// Create a context with which to access the clipboard
-    // TODO: consider replacing Box<dyn Error> with an associated type?
-    fn new() -> Result<Self, Box<dyn Error>>;
+    // TODO: consider replacing Box<dyn Error + Send + Sync> with an associated type?
+    fn new() -> Result<Self, Box<dyn Error + Send + Sync>>;
     /// Method to get the clipboard contents as a String
-    fn get_contents(&mut self) -> Result<String, Box<dyn Error>>;
-    /// Method to set the clipboard contents as a String
-    fn set_contents(&mut self, String) -> Result<(), Box<dyn Error>>;
+    fn get_contents(&mut self) -> Result<String, Box<dyn Error + Send + Sync>>;
+    /// Method to set the clipboard contents as a String, replacing every
+    /// other target on the clipboard
+    fn set_contents(&mut self, contents: String) -> Result<(), Box<dyn Error + Send + Sync>>;
+    /// Name of the backend serving this context, e.g. `"x11"` or `"windows"`
+    fn backend_name(&self) -> &'static str;
+    /// Method to clear the clipboard contents
+    ///
+    /// Clearing a clipboard that is already empty, or that we don't own, is
+    /// not an error, so calling this repeatedly is fine.
+    fn clear(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.set_contents(String::new())
+    }
+    /// Method to clear every clipboard the platform has, e.g. both the
+    /// CLIPBOARD and the PRIMARY selection on X11
+    ///
+    /// Platforms with a single clipboard just `clear` it.
+    fn clear_all(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.clear()
+    }
+    /// Method to set raw bytes as the clipboard text without checking that
+    /// they are valid UTF-8, for legacy consumers expecting e.g. Latin-1
+    ///
+    /// X11 offers the bytes as `STRING` and Windows as `CF_TEXT`, leaving
+    /// their interpretation to the reader. Platforms that only store valid
+    /// text, like macOS, return an error for anything but UTF-8.
+    fn set_text_raw(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.set_contents(String::from_utf8(data)?)
+    }
+    /// When the clipboard contents last changed, e.g. to order clipboard
+    /// history entries
+    ///
+    /// X11 reads the owner's `TIMESTAMP`; `None` where the platform doesn't
+    /// record the time of a change, or the clipboard has no owner. This
+    /// crate's own X11 owner acquires the selection at `CurrentTime` and
+    /// doesn't answer `TIMESTAMP`, so its selections read as `None`.
+    fn last_change_time(&mut self) -> Result<Option<SystemTime>, Box<dyn Error + Send + Sync>> {
+        Ok(None)
+    }
+    /// Method to wait until the last write is visible to other applications
+    ///
+    /// Every current backend writes synchronously (X11 owns the selection
+    /// before `set_*` returns), so this is a no-op unless overridden.
+    fn flush(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
     // TODO: come up with some platform-agnostic API for richer types
     // than just strings (c.f. issue #31)
 
-    fn get_target_contents(&mut self, _: impl ToString) -> Result<Vec<u8>, Box<dyn Error>> {
-        return self.get_contents().map(|s| s.as_bytes().to_vec())
+    fn get_target_contents(&mut self, _: impl ToString) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.get_contents().map(|s| s.as_bytes().to_vec())
+    }
+
+    /// Set the text like `set_contents` and clear the clipboard once `ttl` has
+    /// passed, e.g. for passwords
+    ///
+    /// The clipboard is only cleared if it still holds `data`, so a newer copy
+    /// isn't clobbered. Dropping the handle doesn't stop the timer. On X11 the
+    /// timer shares the context's connections, so the data stays served
+    /// until it expires even if the context is dropped first.
+    fn set_contents_expiring(&mut self, data: String, ttl: Duration) -> Result<ClearHandle, Box<dyn Error + Send + Sync>>
+    where
+        Self: 'static,
+    {
+        self.set_contents(data.clone())?;
+        Ok(ClearHandle::spawn(ttl, move || {
+            // contexts aren't necessarily Send, so the timer opens its own
+            if let Ok(mut ctx) = Self::new() {
+                if ctx.get_contents().ok().as_ref() == Some(&data) {
+                    let _ = ctx.clear();
+                }
+            }
+        }))
+    }
+
+    /// Set the text like `set_contents`, but keep the other targets on the
+    /// clipboard, e.g. to add a plain text version next to an image
+    fn add_contents(&mut self, data: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.set_target_contents(formats::TEXT, data.as_bytes())
+    }
+
+    /// Set `data` under the given target, keeping the other targets on the
+    /// clipboard; `set_multiple_targets` replaces the whole contents instead
+    ///
+    /// X11 can only keep the targets this context set itself, as another
+    /// owner's data isn't copied over. Empty data keeps the target offered
+    /// with no content on X11 and macOS; Windows can't hold zero-sized data
+    /// and refuses it with an error.
+    fn set_target_contents(&mut self, _: impl ToString, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.set_contents(String::from_utf8(data.to_vec())?)
+    }
+
+    /// Like `get_target_contents`, but `None` when the target is absent and
+    /// `Some` with possibly empty data when it is offered
+    ///
+    /// Backends that can't tell an absent target from an empty one return
+    /// `None` for both.
+    fn get_target_contents_opt(&mut self, target: impl ToString) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+        let data = self.get_target_contents(target)?;
+        Ok(if data.is_empty() { None } else { Some(data) })
+    }
+
+    /// Like `get_contents`, but an error instead of an empty string when no
+    /// text is offered at all, e.g. when the clipboard only holds an image
+    ///
+    /// Backends that can't tell absent from empty text fail for empty text
+    /// too, see `get_target_contents_opt`.
+    fn get_contents_strict(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        match self.get_target_contents_opt(formats::TEXT)? {
+            Some(_) => self.get_contents(),
+            None => Err("the clipboard holds no text".into()),
+        }
+    }
+
+    /// SHA-256 of the target's data, or `None` when the target is absent,
+    /// to cheaply compare clipboard entries, e.g. for deduplicating history
+    ///
+    /// No platform can hash data in place, so the target is still read in
+    /// full; only the digest is kept.
+    #[cfg(feature = "hash")]
+    fn target_digest(&mut self, target: impl ToString) -> Result<Option<[u8; 32]>, Box<dyn Error + Send + Sync>> {
+        Ok(self.get_target_contents_opt(target)?.map(|data| {
+            let mut digest = [0; 32];
+            digest.copy_from_slice(&Sha256::digest(&data));
+            digest
+        }))
+    }
+
+    /// Like `get_target_contents`, calling `progress` with the number of bytes
+    /// received so far as a large transfer arrives in chunks
+    ///
+    /// Only X11 transfers data incrementally (INCR); elsewhere `progress` is
+    /// never called.
+    fn get_target_contents_with_progress(&mut self, target: impl ToString, _progress: impl FnMut(usize)) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.get_target_contents(target)
+    }
+
+    /// Poll `targets` every `poll_duration` until one of them holds data and
+    /// return it along with the target, or `None` once `timeout` has passed
+    ///
+    /// Targets are checked in order on every round, so earlier targets win
+    /// when several appear at once.
+    fn wait_for_any_target<T: ToString + Clone>(&mut self, targets: &[T], poll_duration: Duration, timeout: Duration) -> Result<Option<TargetData<T>>, Box<dyn Error + Send + Sync>> {
+        let start = Instant::now();
+        loop {
+            for target in targets {
+                // some platforms report an absent target as an error
+                if let Ok(data) = self.get_target_contents(target.clone()) {
+                    if !data.is_empty() {
+                        return Ok(Some((target.clone(), data)));
+                    }
+                }
+            }
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
+            thread::sleep(poll_duration);
+        }
+    }
+
+    /// Wait for the clipboard to hold text and return it, checking every
+    /// `DEFAULT_POLL_DURATION`, or `None` once `DEFAULT_MAX_WAIT` has passed
+    fn wait_for_contents(&mut self) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let start = Instant::now();
+        loop {
+            // some platforms report an empty clipboard as an error
+            if let Ok(contents) = self.get_contents() {
+                if !contents.is_empty() {
+                    return Ok(Some(contents));
+                }
+            }
+            if start.elapsed() >= DEFAULT_MAX_WAIT {
+                return Ok(None);
+            }
+            thread::sleep(DEFAULT_POLL_DURATION);
+        }
+    }
+
+    /// Replace the clipboard contents with several targets at once
+    ///
+    /// An empty map clears the clipboard, the same as `clear`. The order in
+    /// which readers see the targets is unspecified; on X11 it is whatever
+    /// order `x11-clipboard` lists them in `TARGETS`.
+    ///
+    /// Backends without an implementation of their own hold a single target
+    /// and refuse several with an error rather than dropping all but one.
+    fn set_multiple_targets(&mut self, targets: TargetMap<impl ToString>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if targets.len() > 1 {
+            return Err(format!("the {} backend can't hold several targets at once", self.backend_name()).into());
+        }
+        match targets.into_iter().next() {
+            Some((key, value)) => self.set_target_contents(key, value),
+            None => self.clear(),
+        }
+    }
+
+    /// Rebuild the connection to the display server in place, e.g. after the
+    /// X server restarted, keeping the context's configuration
+    ///
+    /// Whatever the context owned on the clipboard is lost. Backends without
+    /// a persistent connection do nothing.
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
+
+    /// Get the clipboard contents as a String, deriving plain text from the
+    /// HTML or RTF target when no plain text is offered
+    ///
+    /// Tags and control words are stripped on a best-effort basis; the
+    /// result is meant for reading, not for round-tripping.
+    fn get_contents_with_fallback(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        match self.get_contents() {
+            Ok(ref contents) if contents.is_empty() => (),
+            Ok(contents) => return Ok(contents),
+            // only a missing text target calls for a fallback
+            Err(err) => if self.get_target_contents_opt(formats::TEXT)?.is_some() {
+                return Err(err);
+            },
+        }
+        for &(target, to_text) in &[(formats::HTML, rich_text::html_to_text as fn(&str) -> String),
+                                    (formats::RTF, rich_text::rtf_to_text)] {
+            if let Some(data) = self.get_target_contents_opt(target)? {
+                let text = to_text(String::from_utf8_lossy(&data).trim_end_matches('\0'));
+                if !text.is_empty() {
+                    return Ok(text);
+                }
+            }
+        }
+        Ok(String::new())
+    }
+
+    /// Read the HTML target as plain text, with tags stripped and entities
+    /// decoded like `get_contents_with_fallback` does, or `None` when no HTML
+    /// is offered
+    fn get_html_as_text(&mut self) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        Ok(self.get_target_contents_opt(formats::HTML)?
+            .map(|data| rich_text::html_to_text(String::from_utf8_lossy(&data).trim_end_matches('\0'))))
     }
 
-    fn set_target_contents(&mut self, _: impl ToString, data: &[u8]) -> Result<(), Box<dyn Error>> {
-        return self.set_contents(String::from_utf8(data.to_vec())?)
+    /// Whether the clipboard holds data for the target
+    ///
+    /// Reads the whole target unless the backend can check cheaply.
+    fn contains_target(&mut self, target: impl ToString) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(!self.get_target_contents(target)?.is_empty())
     }
 
-    fn set_multiple_targets(&mut self, targets: HashMap<impl ToString, &[u8]>) -> Result<(), Box<dyn Error>> {
-        for (key, value) in targets {
-            return self.set_target_contents(key, value);
+    /// Whether the target is offered, judged from the list of targets alone
+    /// so that owners rendering data on demand aren't asked to render it,
+    /// e.g. to enable a paste button
+    ///
+    /// X11 reads `TARGETS`, Windows checks `IsClipboardFormatAvailable` and
+    /// macOS the pasteboard's types, none of which involve the data. Other
+    /// backends use `list_data_targets` and fail where it does. An offered
+    /// target may still turn out empty when read.
+    fn peek_target(&mut self, target: impl ToString) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let target = target.to_string();
+        Ok(self.list_data_targets()?.contains(&target))
+    }
+
+    /// Whether the clipboard offers text, like `peek_target(formats::TEXT)`
+    fn peek_contents(&mut self) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        self.peek_target(formats::TEXT)
+    }
+
+    /// List the targets holding data, without the platform's bookkeeping
+    ///
+    /// X11 drops the meta targets `TARGETS`, `TIMESTAMP`, `MULTIPLE`,
+    /// `SAVE_TARGETS`, `DELETE` and `INCR`; Windows drops the formats it
+    /// synthesizes (`CF_TEXT`, `CF_OEMTEXT` and `CF_LOCALE` next to
+    /// `CF_UNICODETEXT`) and names formats by id; macOS drops dynamic `dyn.`
+    /// types and the legacy `NS…PboardType` and `CorePasteboardFlavorType`
+    /// aliases of other types. Backends without target listing return an error.
+    fn list_data_targets(&mut self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        Err(format!("listing targets is not supported by the {} backend", self.backend_name()).into())
+    }
+
+    /// Read every target on the clipboard as one consistent snapshot, keyed
+    /// by names accepted by `get_target_contents`
+    ///
+    /// Windows holds the clipboard open for the whole read, and macOS reads
+    /// again whenever the pasteboard changed in between. X11 transfers each
+    /// target separately from the owner, so consistency can't be guaranteed
+    /// and an error is returned there, as on backends without target listing.
+    fn snapshot_atomic(&mut self) -> Result<Vec<TargetData>, Box<dyn Error + Send + Sync>> {
+        Err(format!("atomic snapshots are not supported by the {} backend", self.backend_name()).into())
+    }
+
+    /// Set the same data under `primary` and every alias in one write, e.g.
+    /// text under both `UTF8_STRING` and `text/plain;charset=utf-8`
+    fn set_with_aliases(&mut self, primary: impl ToString, data: &[u8], aliases: &[impl ToString]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut targets = HashMap::new();
+        targets.insert(primary.to_string(), data);
+        for alias in aliases {
+            targets.insert(alias.to_string(), data);
+        }
+        self.set_multiple_targets(targets)
+    }
+
+    /// Set text together with the url it was copied from under
+    /// `formats::SOURCE_URL`, replacing the clipboard contents in one write
+    fn set_text_with_source(&mut self, text: &str, source_url: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let text = text_target_data(text);
+        let mut targets = HashMap::new();
+        targets.insert(formats::TEXT, &text[..]);
+        targets.insert(formats::SOURCE_URL, source_url.as_bytes());
+        self.set_multiple_targets(targets)
+    }
+
+    /// Replace the clipboard contents with JPEG data as is, without
+    /// re-encoding, under the platform's JPEG target (`image/jpeg`, `JFIF` on
+    /// Windows, `public.jpeg` on macOS)
+    fn set_image_jpeg(&mut self, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut targets = HashMap::new();
+        targets.insert(formats::JPEG, data);
+        self.set_multiple_targets(targets)
+    }
+
+    /// Replace the clipboard contents with GIF data as is, without
+    /// re-encoding, under the platform's GIF target (`image/gif`, `GIF` on
+    /// Windows, `com.compuserve.gif` on macOS)
+    fn set_image_gif(&mut self, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut targets = HashMap::new();
+        targets.insert(formats::GIF, data);
+        self.set_multiple_targets(targets)
+    }
+
+    /// Image targets `get_image_bytes` tries, most preferred first;
+    /// `formats::IMAGES` unless the backend was configured otherwise
+    fn image_preference(&self) -> &'static [&'static str] {
+        formats::IMAGES
+    }
+
+    /// Read the encoded bytes of the first image target offered, trying them
+    /// in the order of `image_preference` (by default PNG, then BMP, `CF_DIB`
+    /// on Windows or TIFF on macOS, then JPEG), together with the target they
+    /// were found under. Nothing is decoded, so this works without the
+    /// `image` feature.
+    fn get_image_bytes(&mut self) -> Result<Option<TargetData<&'static str>>, Box<dyn Error + Send + Sync>> {
+        for &target in self.image_preference() {
+            match self.get_target_contents(target) {
+                Ok(data) => if !data.is_empty() {
+                    return Ok(Some((target, data)));
+                },
+                // some platforms report an absent target as an error
+                Err(_) => continue,
+            }
         }
-        return Ok(());
+        Ok(None)
+    }
+
+    /// Read the clipboard image from the first image target the platform
+    /// offers (PNG, then DIB on Windows, TIFF on macOS, BMP or TIFF on X11),
+    /// or `None` when there is no image
+    #[cfg(feature = "image")]
+    fn get_image(&mut self) -> Result<Option<Image>, Box<dyn Error + Send + Sync>> {
+        for &(target, encoding) in image::READ_TARGETS {
+            match self.get_target_contents(target) {
+                Ok(data) => if !data.is_empty() {
+                    return Ok(Some(Image::from_encoded(encoding, data)?));
+                },
+                // some platforms report an absent target as an error
+                Err(_) => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Set an image as PNG, together with DIB on Windows and TIFF on macOS
+    /// for applications that only understand the native format
+    #[cfg(feature = "image")]
+    fn set_image(&mut self, image: &Image) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let encoded = encode_image(image)?;
+        let targets = encoded.iter()
+            .map(|&(target, ref data)| (target, &data[..]))
+            .collect::<HashMap<_, _>>();
+        self.set_multiple_targets(targets)
+    }
+
+    /// Set an image like `set_image` together with text, e.g. a chart and
+    /// its caption, replacing the clipboard contents in one write
+    #[cfg(feature = "image")]
+    fn set_text_and_image(&mut self, text: &str, image: &Image) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let text = text_target_data(text);
+        let encoded = encode_image(image)?;
+        let mut targets = encoded.iter()
+            .map(|&(target, ref data)| (target, &data[..]))
+            .collect::<HashMap<_, _>>();
+        targets.insert(formats::TEXT, &text[..]);
+        self.set_multiple_targets(targets)
     }
 }
 
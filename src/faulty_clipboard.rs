@@ -0,0 +1,122 @@
+/*
+Copyright 2016 Avraham Weinstock
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use common::{ClipboardProvider, TargetMap};
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Clipboard methods a fault can be injected into
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Method {
+    GetContents,
+    SetContents,
+    Clear,
+    GetTargetContents,
+    SetTargetContents,
+    SetMultipleTargets,
+}
+
+/// Clipboard wrapping another one and failing chosen calls deterministically,
+/// to exercise error handling without mocking the OS.
+///
+/// Calls that aren't made to fail are passed through to the wrapped context.
+pub struct FaultyClipboardContext<C>
+where
+    C: ClipboardProvider,
+{
+    inner: C,
+    calls: HashMap<Method, usize>,
+    faults: Vec<(Method, usize, String)>,
+}
+
+impl<C> FaultyClipboardContext<C>
+where
+    C: ClipboardProvider,
+{
+    /// Wrap an existing context
+    pub fn from_context(inner: C) -> FaultyClipboardContext<C> {
+        FaultyClipboardContext { inner, calls: HashMap::new(), faults: Vec::new() }
+    }
+
+    /// Make the `n`th call (counting from 1) to `method` fail with `error`
+    /// instead of reaching the wrapped context
+    pub fn fail_nth(&mut self, method: Method, n: usize, error: &str) {
+        self.faults.push((method, n, error.to_owned()));
+    }
+
+    /// How often `method` has been called so far, failed calls included
+    pub fn calls(&self, method: Method) -> usize {
+        self.calls.get(&method).cloned().unwrap_or(0)
+    }
+
+    /// Access the wrapped context
+    pub fn inner(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    fn call(&mut self, method: Method) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let calls = self.calls.entry(method).or_insert(0);
+        *calls += 1;
+        let calls = *calls;
+        match self.faults.iter().find(|&&(m, n, _)| m == method && n == calls) {
+            Some((_, _, error)) => Err(error.clone().into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<C> ClipboardProvider for FaultyClipboardContext<C>
+where
+    C: ClipboardProvider,
+{
+    fn new() -> Result<FaultyClipboardContext<C>, Box<dyn Error + Send + Sync>> {
+        Ok(FaultyClipboardContext::from_context(C::new()?))
+    }
+
+    fn get_contents(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.call(Method::GetContents)?;
+        self.inner.get_contents()
+    }
+
+    fn set_contents(&mut self, data: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.call(Method::SetContents)?;
+        self.inner.set_contents(data)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.inner.backend_name()
+    }
+
+    fn clear(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.call(Method::Clear)?;
+        self.inner.clear()
+    }
+
+    fn get_target_contents(&mut self, target: impl ToString) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.call(Method::GetTargetContents)?;
+        self.inner.get_target_contents(target)
+    }
+
+    fn set_target_contents(&mut self, target: impl ToString, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.call(Method::SetTargetContents)?;
+        self.inner.set_target_contents(target, data)
+    }
+
+    fn set_multiple_targets(&mut self, targets: TargetMap<impl ToString>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.call(Method::SetMultipleTargets)?;
+        self.inner.set_multiple_targets(targets)
+    }
+}
@@ -0,0 +1,103 @@
+/*
+Copyright 2016 Avraham Weinstock
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Names of common clipboard targets on the current platform, for use with
+//! `get_target_contents` and `set_target_contents`.
+
+pub use self::platform::*;
+
+/// Names accepted as UTF-8 text on every platform: X11's `UTF8_STRING`, the
+/// MIME type `text/plain;charset=utf-8` and macOS' `public.utf8-plain-text`.
+///
+/// Where an alias isn't a target of its own it stands for `TEXT`, and on
+/// Windows data under an alias is converted to and from `CF_UNICODETEXT`.
+pub const TEXT_ALIASES: &[&str] = &["UTF8_STRING", "text/plain;charset=utf-8", "public.utf8-plain-text"];
+
+// a text alias the platform doesn't know as a target is read and written as TEXT
+#[cfg(all(unix, not(any(target_os="android", target_os="emscripten"))))]
+pub(crate) fn resolve_text_alias(target: &str) -> &str {
+    if TEXT_ALIASES.contains(&target) && !UTF8_TEXT.contains(&target) {
+        TEXT
+    } else {
+        target
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    /// `CF_UNICODETEXT`, holding NUL-terminated UTF-16
+    pub const TEXT: &str = "13";
+    pub const HTML: &str = "HTML Format";
+    pub const RTF: &str = "Rich Text Format";
+    pub const PNG: &str = "PNG";
+    /// `CF_HDROP`
+    pub const URI_LIST: &str = "15";
+    pub const JPEG: &str = "JFIF";
+    pub const GIF: &str = "GIF";
+    /// `CF_DIB`, a BITMAPINFO followed by the pixels
+    pub const DIB: &str = "8";
+    /// `CF_DIBV5`, like `CF_DIB` with a BITMAPV5HEADER carrying alpha and color space
+    pub const DIBV5: &str = "17";
+    /// DWORD telling whether files on the clipboard were cut or copied
+    pub const DROP_EFFECT: &str = "Preferred DropEffect";
+    /// UTF-8 url of the page text was copied from; there is no convention
+    /// on Windows besides `SourceURL` in `HTML Format`, so Chromium's name is used
+    pub const SOURCE_URL: &str = "chromium/x-source-url";
+
+    /// Image targets in the order `get_image_bytes` tries them by default
+    pub const IMAGES: &[&str] = &[PNG, DIB, JPEG];
+}
+
+#[cfg(target_os="macos")]
+mod platform {
+    pub const TEXT: &str = "public.utf8-plain-text";
+    pub const HTML: &str = "public.html";
+    pub const RTF: &str = "public.rtf";
+    pub const PNG: &str = "public.png";
+    pub const URI_LIST: &str = "public.file-url";
+    pub const JPEG: &str = "public.jpeg";
+    pub const GIF: &str = "com.compuserve.gif";
+    pub const TIFF: &str = "public.tiff";
+    /// UTF-8 url of the page text was copied from, as set by Chromium
+    pub const SOURCE_URL: &str = "org.chromium.source-url";
+
+    pub(crate) const UTF8_TEXT: &[&str] = &[TEXT];
+    /// Image targets in the order `get_image_bytes` tries them by default
+    pub const IMAGES: &[&str] = &[PNG, TIFF, JPEG];
+}
+
+#[cfg(not(any(windows, target_os="macos")))]
+mod platform {
+    pub const TEXT: &str = "UTF8_STRING";
+    pub const HTML: &str = "text/html";
+    pub const RTF: &str = "text/rtf";
+    pub const PNG: &str = "image/png";
+    pub const URI_LIST: &str = "text/uri-list";
+    pub const JPEG: &str = "image/jpeg";
+    pub const GIF: &str = "image/gif";
+    pub const BMP: &str = "image/bmp";
+    pub const TIFF: &str = "image/tiff";
+    /// Files as used by GNOME, preceded by a `cut` or `copy` line
+    pub const GNOME_FILES: &str = "x-special/gnome-copied-files";
+    /// `1` when files were cut, as used by KDE
+    pub const KDE_CUT: &str = "application/x-kde-cutselection";
+    /// UTF-8 url of the page text was copied from, as set by Chromium
+    pub const SOURCE_URL: &str = "chromium/x-source-url";
+
+    pub(crate) const UTF8_TEXT: &[&str] = &[TEXT, "text/plain;charset=utf-8"];
+    /// Image targets in the order `get_image_bytes` tries them by default
+    pub const IMAGES: &[&str] = &[PNG, BMP, JPEG];
+}
@@ -0,0 +1,155 @@
+/*
+Copyright 2016 Avraham Weinstock
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+
+//! Clipboard images, available with the `image` feature.
+
+use formats;
+use image_crate::{self, DynamicImage, ImageDecoder, ImageFormat, ImageOutputFormat, RgbaImage};
+use image_crate::codecs::bmp::BmpDecoder;
+use image_crate::io::Reader;
+use std::cell::{Ref, RefCell};
+use std::error::Error;
+use std::io::Cursor;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Encoding {
+    /// BMP without its file header, as in `CF_DIB`
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Dib,
+    Format(ImageFormat),
+}
+
+// image targets in order of preference when reading, and the ones written
+// Windows converts between CF_BITMAP, CF_DIB and CF_DIBV5 on demand, so reading
+// the DIB variants also covers images only offered as a CF_BITMAP handle, and
+// writing CF_DIB makes the other two available
+#[cfg(windows)]
+pub(crate) const READ_TARGETS: &[(&str, Encoding)] = &[
+    (formats::PNG, Encoding::Format(ImageFormat::Png)),
+    (formats::DIBV5, Encoding::Dib),
+    (formats::DIB, Encoding::Dib),
+];
+#[cfg(windows)]
+pub(crate) const WRITE_TARGETS: &[(&str, Encoding)] = &[
+    (formats::PNG, Encoding::Format(ImageFormat::Png)),
+    (formats::DIB, Encoding::Dib),
+];
+
+#[cfg(target_os="macos")]
+pub(crate) const READ_TARGETS: &[(&str, Encoding)] = &[
+    (formats::PNG, Encoding::Format(ImageFormat::Png)),
+    (formats::TIFF, Encoding::Format(ImageFormat::Tiff)),
+];
+#[cfg(target_os="macos")]
+pub(crate) const WRITE_TARGETS: &[(&str, Encoding)] = READ_TARGETS;
+
+#[cfg(not(any(windows, target_os="macos")))]
+pub(crate) const READ_TARGETS: &[(&str, Encoding)] = &[
+    (formats::PNG, Encoding::Format(ImageFormat::Png)),
+    (formats::BMP, Encoding::Format(ImageFormat::Bmp)),
+    (formats::TIFF, Encoding::Format(ImageFormat::Tiff)),
+];
+#[cfg(not(any(windows, target_os="macos")))]
+pub(crate) const WRITE_TARGETS: &[(&str, Encoding)] = &[
+    (formats::PNG, Encoding::Format(ImageFormat::Png)),
+];
+
+/// An image read from or written to the clipboard.
+///
+/// Images read from the clipboard keep the bytes the platform offered and
+/// are only decoded to pixels once those are needed.
+pub struct Image {
+    encoded: Option<(Encoding, Vec<u8>)>,
+    width: u32,
+    height: u32,
+    pixels: RefCell<Option<RgbaImage>>,
+}
+
+impl Image {
+    /// Create an image from RGBA pixels with 8 bits per channel, `None` when
+    /// `rgba` doesn't hold `width * height` pixels
+    pub fn from_rgba(width: u32, height: u32, rgba: Vec<u8>) -> Option<Image> {
+        let pixels = RgbaImage::from_raw(width, height, rgba)?;
+        Some(Image { encoded: None, width, height, pixels: RefCell::new(Some(pixels)) })
+    }
+
+    pub(crate) fn from_encoded(encoding: Encoding, data: Vec<u8>) -> Result<Image, Box<dyn Error + Send + Sync>> {
+        let (width, height) = match encoding {
+            Encoding::Dib => BmpDecoder::new_without_file_header(Cursor::new(&data))?.dimensions(),
+            Encoding::Format(format) => Reader::with_format(Cursor::new(&data), format).into_dimensions()?,
+        };
+        Ok(Image { encoded: Some((encoding, data)), width, height, pixels: RefCell::new(None) })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The image encoded as PNG
+    pub fn to_png(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.encode(Encoding::Format(ImageFormat::Png))
+    }
+
+    /// The image as RGBA pixels with 8 bits per channel, row by row
+    pub fn to_rgba(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        Ok(self.pixels()?.clone().into_raw())
+    }
+
+    pub(crate) fn encode(&self, encoding: Encoding) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        if let Some((Encoding::Format(format), ref data)) = self.encoded {
+            if let Encoding::Format(wanted) = encoding {
+                if format == wanted {
+                    return Ok(data.clone());
+                }
+            }
+        }
+        let mut encoded = Vec::new();
+        match encoding {
+            Encoding::Dib => {
+                // 24 bit pixels keep to the plain BITMAPINFOHEADER every reader understands
+                let rgb = DynamicImage::ImageRgba8(self.pixels()?.clone()).to_rgb8();
+                rgb.write_to(&mut Cursor::new(&mut encoded), ImageOutputFormat::Bmp)?;
+                // drop the BITMAPFILEHEADER
+                encoded.drain(..14);
+            }
+            Encoding::Format(format) => {
+                self.pixels()?.write_to(&mut Cursor::new(&mut encoded), format)?;
+            }
+        }
+        Ok(encoded)
+    }
+
+    fn pixels(&self) -> Result<Ref<'_, RgbaImage>, Box<dyn Error + Send + Sync>> {
+        if self.pixels.borrow().is_none() {
+            let decoded = match self.encoded {
+                Some((Encoding::Dib, ref data)) => {
+                    DynamicImage::from_decoder(BmpDecoder::new_without_file_header(Cursor::new(data))?)?
+                }
+                Some((Encoding::Format(format), ref data)) => {
+                    image_crate::load_from_memory_with_format(data, format)?
+                }
+                None => unreachable!("images are created either encoded or from pixels"),
+            };
+            *self.pixels.borrow_mut() = Some(decoded.to_rgba8());
+        }
+        Ok(Ref::map(self.pixels.borrow(), |pixels| pixels.as_ref().unwrap()))
+    }
+}
@@ -21,9 +21,19 @@ limitations under the License.
 
 #[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten"))))]
 extern crate x11_clipboard as x11_clipboard_crate;
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten"))))]
+extern crate libc;
 
 #[cfg(windows)]
 extern crate clipboard_win;
+#[cfg(windows)]
+extern crate winapi;
+
+#[cfg(feature = "image")]
+extern crate image as image_crate;
+
+#[cfg(feature = "hash")]
+extern crate sha2;
 
 #[cfg(target_os="macos")]
 #[macro_use]
@@ -34,7 +44,17 @@ extern crate objc_id;
 extern crate objc_foundation;
 
 mod common;
-pub use common::ClipboardProvider;
+pub use common::{ClearHandle, ClipboardProvider, FileFormat, FileList, TargetData, TargetMap, DEFAULT_MAX_READ_BYTES, DEFAULT_MAX_WAIT, DEFAULT_POLL_DURATION};
+
+mod rich_text;
+
+mod builder;
+pub use builder::ClipboardContextBuilder;
+
+pub mod formats;
+
+#[cfg(feature = "image")]
+pub mod image;
 
 #[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten"))))]
 pub mod x11_clipboard;
@@ -47,20 +67,957 @@ pub mod osx_clipboard;
 
 pub mod nop_clipboard;
 
-#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten"))))]
+pub mod tee_clipboard;
+
+#[cfg(feature = "test-util")]
+pub mod faulty_clipboard;
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
 pub type ClipboardContext = x11_clipboard::X11ClipboardContext;
-#[cfg(windows)]
+#[cfg(all(windows, not(feature = "nop")))]
 pub type ClipboardContext = windows_clipboard::WindowsClipboardContext;
-#[cfg(target_os="macos")]
+#[cfg(all(target_os="macos", not(feature = "nop")))]
 pub type ClipboardContext = osx_clipboard::OSXClipboardContext;
-#[cfg(target_os="android")]
+#[cfg(all(target_os="android", not(feature = "nop")))]
 pub type ClipboardContext = nop_clipboard::NopClipboardContext; // TODO: implement AndroidClipboardContext (see #52)
-#[cfg(not(any(unix, windows, target_os="macos", target_os="android", target_os="emscripten")))]
+// the nop feature guarantees the system clipboard is never touched, e.g. on CI
+#[cfg(any(feature = "nop", not(any(unix, windows, target_os="macos", target_os="android", target_os="emscripten"))))]
 pub type ClipboardContext = nop_clipboard::NopClipboardContext;
 
+// the system clipboard is shared by the whole process and the harness runs
+// tests in parallel, so every test touching it holds this lock
+#[cfg(all(test, not(feature = "nop")))]
+static SYSTEM_CLIPBOARD: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(all(test, not(feature = "nop")))]
+fn lock_system_clipboard() -> std::sync::MutexGuard<'static, ()> {
+    // a failed test must not fail every test after it
+    SYSTEM_CLIPBOARD.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_set_files_with_effect_keeps_files() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_files_with_effect(&[std::env::temp_dir()], true).unwrap();
+    assert!(ctx.get_files().unwrap() == vec![std::env::temp_dir()]);
+    assert!(ctx.drop_effect().unwrap() == Some(true));
+}
+
+#[cfg(feature = "nop")]
+#[test]
+fn test_nop_feature_forces_nop_backend() {
+    let ctx = ClipboardContext::new().unwrap();
+    assert!(ctx.backend_name() == "nop");
+}
+
+#[cfg(not(feature = "nop"))]
 #[test]
 fn test_clipboard() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+    assert!(ctx.get_contents().unwrap() == "some string");
+}
+
+#[cfg(not(feature = "nop"))]
+#[test]
+fn test_clear_twice() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+    ctx.clear().unwrap();
+    ctx.clear().unwrap();
+    assert!(ctx.get_contents().unwrap_or_default() == "");
+}
+
+#[cfg(not(feature = "nop"))]
+#[test]
+fn test_rich_text_fallback() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.clear().unwrap();
+    ctx.set_target_contents(formats::HTML, b"<p>some &amp; <b>string</b></p><br>more").unwrap();
+    assert!(ctx.get_contents_with_fallback().unwrap() == "some & string\nmore");
+    assert!(ctx.get_html_as_text().unwrap() == Some("some & string\nmore".to_owned()));
+}
+
+#[cfg(not(feature = "nop"))]
+#[test]
+fn test_empty_multiple_targets_clear() {
+    let _clipboard = lock_system_clipboard();
+    use std::collections::HashMap;
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+    ctx.set_multiple_targets(HashMap::<&str, &[u8]>::new()).unwrap();
+    assert!(ctx.get_contents().unwrap_or_default() == "");
+}
+
+// Windows can't hold zero-sized data
+#[cfg(all(unix, not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_empty_target_is_distinguished_from_absent() {
+    let _clipboard = lock_system_clipboard();
+    use std::collections::HashMap;
+    let mut ctx = ClipboardContext::new().unwrap();
+    let mut targets = HashMap::new();
+    targets.insert(formats::HTML, &b""[..]);
+    targets.insert(formats::RTF, &b"{\\rtf1 some string}"[..]);
+    ctx.set_multiple_targets(targets).unwrap();
+    assert!(ctx.get_target_contents_opt(formats::PNG).unwrap().is_none());
+    assert!(ctx.get_target_contents_opt(formats::HTML).unwrap().is_some());
+}
+
+#[cfg(all(unix, not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_strict_contents_tell_missing_text_from_empty() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.clear().unwrap();
+    ctx.set_target_contents(formats::HTML, b"<b>some string</b>").unwrap();
+    assert!(ctx.get_contents_strict().is_err());
+    ctx.set_contents(String::new()).unwrap();
+    assert!(ctx.get_contents_strict().unwrap().is_empty());
+}
+
+#[cfg(not(feature = "nop"))]
+#[test]
+fn test_set_text_with_source() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_text_with_source("some string", "https://example.com/").unwrap();
+    assert!(ctx.get_contents().unwrap() == "some string");
+    assert!(ctx.get_target_contents(formats::SOURCE_URL).unwrap().starts_with(b"https://example.com/"));
+}
+
+#[cfg(not(feature = "nop"))]
+#[test]
+fn test_builder() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContextBuilder::new()
+        .read_timeout(std::time::Duration::from_secs(1))
+        .x11_property("RUST_CLIPBOARD_BUILDER_TEST")
+        .max_read_bytes(DEFAULT_MAX_READ_BYTES)
+        .image_preference(formats::IMAGES)
+        .build()
+        .unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+    assert!(ctx.get_contents().unwrap() == "some string");
+}
+
+#[cfg(not(feature = "nop"))]
+#[test]
+fn test_set_with_aliases() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_with_aliases(formats::HTML, b"<b>some string</b>", &["rust-clipboard/html-alias"]).unwrap();
+    assert!(ctx.get_target_contents(formats::HTML).unwrap().starts_with(b"<b>some string</b>"));
+    assert!(ctx.get_target_contents("rust-clipboard/html-alias").unwrap().starts_with(b"<b>some string</b>"));
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_faulty_clipboard_fails_nth_call() {
+    use faulty_clipboard::{FaultyClipboardContext, Method};
+    let mut ctx: FaultyClipboardContext<nop_clipboard::NopClipboardContext> = ClipboardProvider::new().unwrap();
+    ctx.fail_nth(Method::SetContents, 2, "injected");
+    assert!(ctx.set_contents("some string".to_owned()).is_ok());
+    assert!(ctx.set_contents("some string".to_owned()).unwrap_err().to_string() == "injected");
+    assert!(ctx.set_contents("some string".to_owned()).is_ok());
+    assert!(ctx.calls(Method::SetContents) == 3);
+}
+
+#[test]
+fn test_tee_writes_both_and_reads_either() {
+    use std::cell::RefCell;
+    use std::error::Error;
+    use std::rc::Rc;
+    use tee_clipboard::TeeClipboardContext;
+    // keeps its contents where the test can still see them
+    struct MemoryClipboard(Rc<RefCell<String>>);
+    impl ClipboardProvider for MemoryClipboard {
+        fn new() -> Result<MemoryClipboard, Box<dyn Error + Send + Sync>> { Ok(MemoryClipboard(Rc::default())) }
+        fn get_contents(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> { Ok(self.0.borrow().clone()) }
+        fn set_contents(&mut self, data: String) -> Result<(), Box<dyn Error + Send + Sync>> { *self.0.borrow_mut() = data; Ok(()) }
+        fn backend_name(&self) -> &'static str { "memory" }
+    }
+    let (first, second) = (Rc::new(RefCell::new(String::new())), Rc::new(RefCell::new(String::new())));
+    let mut ctx = TeeClipboardContext::from_contexts(MemoryClipboard(first.clone()), MemoryClipboard(second.clone()));
+    ctx.set_contents("some string".to_owned()).unwrap();
+    assert!(*first.borrow() == "some string" && *second.borrow() == "some string");
+    // the second clipboard only answers while the first is empty
+    *second.borrow_mut() = "other string".to_owned();
+    assert!(ctx.get_contents().unwrap() == "some string");
+    first.borrow_mut().clear();
+    assert!(ctx.get_contents().unwrap() == "other string");
+    assert!(ctx.get_target_contents(formats::TEXT).unwrap() == b"other string");
+}
+
+#[cfg(all(feature = "hash", not(feature = "nop")))]
+#[test]
+fn test_target_digest() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_target_contents(formats::HTML, b"<b>some string</b>").unwrap();
+    let first = ctx.target_digest(formats::HTML).unwrap().unwrap();
+    ctx.set_target_contents(formats::HTML, b"<b>some string</b>").unwrap();
+    assert!(ctx.target_digest(formats::HTML).unwrap().unwrap() == first);
+    ctx.set_target_contents(formats::HTML, b"<b>other string</b>").unwrap();
+    assert!(ctx.target_digest(formats::HTML).unwrap().unwrap() != first);
+}
+
+#[cfg(not(feature = "nop"))]
+#[test]
+fn test_wait_for_any_target() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.clear().unwrap();
+    ctx.set_target_contents(formats::HTML, b"<b>some string</b>").unwrap();
+    let (target, data) = ctx.wait_for_any_target(&[formats::PNG, formats::HTML], DEFAULT_POLL_DURATION, DEFAULT_MAX_WAIT)
+        .unwrap()
+        .unwrap();
+    assert!(target == formats::HTML && data.starts_with(b"<b>some string</b>"));
+}
+
+#[cfg(not(feature = "nop"))]
+#[test]
+fn test_wait_for_contents() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+    assert!(ctx.wait_for_contents().unwrap() == Some("some string".to_owned()));
+}
+
+#[cfg(all(any(unix, windows), not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_oversized_target_is_refused() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_max_read_bytes(1024);
+    ctx.set_target_contents(formats::HTML, &[b'x'; 4096]).unwrap();
+    assert!(ctx.get_target_contents(formats::HTML).is_err());
+    ctx.set_max_read_bytes(DEFAULT_MAX_READ_BYTES);
+    assert!(ctx.get_target_contents(formats::HTML).unwrap().len() >= 4096);
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_multiple_targets_keep_every_format() {
+    let _clipboard = lock_system_clipboard();
+    use std::collections::HashMap;
+    let mut ctx = ClipboardContext::new().unwrap();
+    let mut targets = HashMap::new();
+    targets.insert("rust-clipboard test format", &b"first"[..]);
+    targets.insert("HTML Format", &b"<b>second</b>"[..]);
+    ctx.set_multiple_targets(targets).unwrap();
+    assert!(ctx.get_target_contents("rust-clipboard test format").unwrap().starts_with(b"first"));
+    assert!(ctx.get_target_contents("HTML Format").unwrap().starts_with(b"<b>second</b>"));
+    assert!(ctx.set_target_contents(formats::HTML, b"").is_err());
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_failed_multiple_targets_leave_clipboard_untouched() {
+    let _clipboard = lock_system_clipboard();
+    use std::collections::HashMap;
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+    let mut targets = HashMap::new();
+    targets.insert("rust-clipboard test format", "other string".as_bytes());
+    // 0 is never a valid clipboard format
+    targets.insert("0", "invalid".as_bytes());
+    assert!(ctx.set_multiple_targets(targets).is_err());
+    assert!(ctx.get_contents().unwrap() == "some string");
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_concurrent_writes_with_retries() {
+    let _clipboard = lock_system_clipboard();
+    use std::thread;
+    use std::time::Duration;
+    let handles: Vec<_> = (0..8).map(|i| thread::spawn(move || {
+        let mut ctx = ClipboardContext::new().unwrap();
+        ctx.set_retries(100, Duration::from_millis(5));
+        for _ in 0..20 {
+            ctx.set_contents(format!("thread {}", i)).unwrap();
+            ctx.get_contents().unwrap();
+        }
+    })).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_contains_target_matches_listed_targets() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+    for format in ctx.list_user_targets().unwrap() {
+        assert!(ctx.contains_target(format).unwrap());
+    }
+    assert!(!ctx.contains_target("rust-clipboard test format").unwrap());
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_get_files_keeps_long_unicode_paths() {
+    let _clipboard = lock_system_clipboard();
+    use std::path::PathBuf;
+    let long = PathBuf::from(format!(r"\\?\C:\{}\ファイル é.txt", "long directory name ".repeat(15)));
+    assert!(long.as_os_str().len() > 260);
+    let paths = vec![long, PathBuf::from(r"C:\Users\Zoë\notes.txt")];
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_files_unchecked(&paths).unwrap();
+    assert!(ctx.get_files().unwrap() == paths);
+    ctx.clear().unwrap();
+    assert!(ctx.get_files().unwrap().is_empty());
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_windows_get_files_as_urls() {
+    let _clipboard = lock_system_clipboard();
+    use std::path::PathBuf;
+    let paths = vec![PathBuf::from(r"C:\My Files\é.txt"), PathBuf::from(r"\\server\share\a.txt")];
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_files_unchecked(&paths).unwrap();
+    let urls = vec!["file:///C:/My%20Files/%C3%A9.txt".to_owned(), "file://server/share/a.txt".to_owned()];
+    assert!(ctx.get_files_as(FileFormat::Urls).unwrap() == FileList::Urls(urls));
+    assert!(ctx.get_files_as(FileFormat::Paths).unwrap() == FileList::Paths(paths));
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_fail_fast_write_reports_busy_clipboard() {
+    let _clipboard = lock_system_clipboard();
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use windows_clipboard::{ClipboardBusy, WritePolicy};
+    let (opened, wait_opened) = mpsc::channel();
+    let holder = thread::spawn(move || {
+        let _clipboard = clipboard_win::Clipboard::new().unwrap();
+        opened.send(()).unwrap();
+        thread::sleep(Duration::from_millis(500));
+    });
+    wait_opened.recv().unwrap();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_write_policy(WritePolicy::FailFast);
+    let e = ctx.set_contents("some string".to_owned()).unwrap_err();
+    let busy = e.downcast_ref::<ClipboardBusy>().unwrap();
+    assert!(busy.policy == WritePolicy::FailFast && busy.attempts == 1);
+    holder.join().unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_session_shares_one_open_clipboard() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    let session = ctx.session().unwrap();
+    session.clear().unwrap();
+    session.set("rust-clipboard test format", b"some data").unwrap();
+    assert!(session.get("rust-clipboard test format").unwrap().starts_with(b"some data"));
+    assert!(session.list().len() == 1);
+    session.clear().unwrap();
+    assert!(session.list().is_empty());
+}
+
+#[cfg(all(windows, not(feature = "nop")))]
+#[test]
+fn test_with_open_batches_formats() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.with_open(|clipboard| {
+        clipboard.empty().unwrap();
+        clipboard.set(clipboard_win::register_format("rust-clipboard test format").unwrap(), b"first").unwrap();
+        clipboard.set(clipboard_win::register_format("HTML Format").unwrap(), b"<b>second</b>").unwrap();
+    }).unwrap();
+    assert!(ctx.get_target_contents("rust-clipboard test format").unwrap().starts_with(b"first"));
+    assert!(ctx.get_target_contents("HTML Format").unwrap().starts_with(b"<b>second</b>"));
+}
+
+// every name the backend treats as UTF-8 text must read back as text from
+// another context, whichever one it was set under
+#[cfg(all(unix, not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_text_targets_round_trip_across_contexts() {
+    let _clipboard = lock_system_clipboard();
+    for &target in formats::UTF8_TEXT {
+        let mut writer = ClipboardContext::new().unwrap();
+        let mut reader = ClipboardContext::new().unwrap();
+        writer.set_target_contents(target, "some string".as_bytes()).unwrap();
+        assert!(reader.get_target_contents(target).unwrap() == b"some string");
+        if target == formats::TEXT {
+            assert!(reader.get_contents().unwrap() == "some string");
+        }
+    }
+}
+
+#[cfg(all(any(unix, windows), not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_list_data_targets() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.clear().unwrap();
+    ctx.set_target_contents("rust-clipboard test format", b"some data").unwrap();
+    let targets = ctx.list_data_targets().unwrap();
+    assert!(targets.len() == 1);
+    // Windows names formats by id
+    #[cfg(not(windows))]
+    assert!(targets[0] == "rust-clipboard test format");
+}
+
+#[cfg(all(any(windows, target_os="macos"), not(feature = "nop")))]
+#[test]
+fn test_snapshot_atomic() {
+    let _clipboard = lock_system_clipboard();
+    use std::collections::HashMap;
+    let mut ctx = ClipboardContext::new().unwrap();
+    let mut targets = HashMap::new();
+    targets.insert(formats::HTML, &b"<b>some string</b>"[..]);
+    targets.insert(formats::PNG, &b"not really a png"[..]);
+    ctx.set_multiple_targets(targets).unwrap();
+    let snapshot = ctx.snapshot_atomic().unwrap();
+    assert!(snapshot.iter().any(|&(_, ref data)| data.starts_with(b"<b>some string</b>")));
+    assert!(snapshot.iter().any(|&(_, ref data)| data.starts_with(b"not really a png")));
+}
+
+#[cfg(all(target_os="macos", feature = "image", not(feature = "nop")))]
+#[test]
+fn test_osx_image_off_main_thread() {
+    let _clipboard = lock_system_clipboard();
+    std::thread::spawn(|| {
+        let pixels = vec![255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 255, 255, 255];
+        let mut ctx = ClipboardContext::new().unwrap();
+        ctx.set_image(&image::Image::from_rgba(2, 2, pixels.clone()).unwrap()).unwrap();
+        assert!(ctx.get_image().unwrap().unwrap().to_rgba().unwrap() == pixels);
+    }).join().unwrap();
+}
+
+// encoding round trip of every platform, no clipboard needed
+#[cfg(feature = "image")]
+#[test]
+fn test_image_encoding_round_trip() {
+    use image::{Encoding, Image};
+    use image_crate::ImageFormat;
+    // opaque, as CF_DIB doesn't keep alpha
+    let pixels = vec![255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 255, 255, 255];
+    let image = Image::from_rgba(2, 2, pixels.clone()).unwrap();
+    let dib = Image::from_encoded(Encoding::Dib, image.encode(Encoding::Dib).unwrap()).unwrap();
+    assert!(dib.width() == 2 && dib.height() == 2);
+    assert!(dib.to_rgba().unwrap() == pixels);
+    let png = Image::from_encoded(Encoding::Format(ImageFormat::Png), image.to_png().unwrap()).unwrap();
+    assert!(png.to_rgba().unwrap() == pixels);
+}
+
+#[cfg(all(feature = "image", not(feature = "nop")))]
+#[test]
+fn test_set_text_and_image() {
+    let _clipboard = lock_system_clipboard();
+    let pixels = vec![255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 255, 255, 255];
+    let image = image::Image::from_rgba(2, 2, pixels.clone()).unwrap();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_text_and_image("some caption", &image).unwrap();
+    assert!(ctx.get_contents().unwrap() == "some caption");
+    let pasted = ctx.get_image().unwrap().unwrap();
+    assert!(pasted.width() == 2 && pasted.height() == 2);
+    assert!(pasted.to_rgba().unwrap() == pixels);
+}
+
+#[cfg(all(any(unix, windows), not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_image_bytes_fall_back_to_jpeg() {
+    let _clipboard = lock_system_clipboard();
+    let jpeg = b"\xff\xd8\xff\xe0 not really a jpeg";
     let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_image_jpeg(jpeg).unwrap();
+    // Windows may hand back the data padded to its allocation size
+    let (target, data) = ctx.get_image_bytes().unwrap().unwrap();
+    assert!(target == formats::JPEG && data.starts_with(jpeg));
+    ctx.clear().unwrap();
+    assert!(ctx.get_image_bytes().unwrap().is_none());
+}
+
+#[cfg(all(target_os="macos", not(feature = "nop")))]
+#[test]
+fn test_osx_reads_representations_of_later_items() {
+    let _clipboard = lock_system_clipboard();
+    use objc::runtime::{Class, Object};
+    use objc_foundation::{INSData, INSString, NSData, NSString};
+    let mut ctx = ClipboardContext::new().unwrap();
+    unsafe {
+        let item_class = Class::get("NSPasteboardItem").unwrap();
+        let first: *mut Object = msg_send![item_class, new];
+        let second: *mut Object = msg_send![item_class, new];
+        let _: bool = msg_send![first, setData:&*NSData::with_bytes(b"<b>first</b>") forType:&*NSString::from_str(formats::HTML)];
+        let _: bool = msg_send![second, setData:&*NSData::with_bytes(b"not really a png") forType:&*NSString::from_str(formats::PNG)];
+        let _: bool = msg_send![second, setData:&*NSData::with_bytes(b"not really a tiff") forType:&*NSString::from_str(formats::TIFF)];
+        let items = [first, second];
+        let array: *mut Object = msg_send![Class::get("NSArray").unwrap(), arrayWithObjects:items.as_ptr() count:items.len()];
+        let _: isize = msg_send![ctx.pasteboard(), clearContents];
+        let _: bool = msg_send![ctx.pasteboard(), writeObjects:array];
+        let _: () = msg_send![first, release];
+        let _: () = msg_send![second, release];
+    }
+    assert!(ctx.get_target_contents(formats::TIFF).unwrap() == b"not really a tiff");
+    assert!(ctx.get_target_contents_opt(formats::PNG).unwrap() == Some(b"not really a png".to_vec()));
+    assert!(ctx.peek_target(formats::TIFF).unwrap());
+    assert!(ctx.list_data_targets().unwrap().iter().any(|target| target == formats::TIFF));
+    assert!(ctx.get_all_contents().unwrap().contains(&(formats::TIFF.to_owned(), b"not really a tiff".to_vec())));
+}
+
+#[cfg(all(target_os="macos", not(feature = "nop")))]
+#[test]
+fn test_osx_get_files_as() {
+    let _clipboard = lock_system_clipboard();
+    use std::path::PathBuf;
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_target_contents(formats::URI_LIST, b"file:///tmp/a%20b.txt").unwrap();
+    assert!(ctx.get_files_as(FileFormat::Paths).unwrap() == FileList::Paths(vec![PathBuf::from("/tmp/a b.txt")]));
+    assert!(ctx.get_files_as(FileFormat::Urls).unwrap() == FileList::Urls(vec!["file:///tmp/a%20b.txt".to_owned()]));
+}
+
+#[cfg(all(any(unix, windows), not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_set_contents_expiring() {
+    let _clipboard = lock_system_clipboard();
+    use std::time::Duration;
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_contents_expiring("secret".to_owned(), Duration::from_millis(100)).unwrap().wait();
+    assert!(ctx.get_contents().map_or(true, |contents| contents.is_empty()));
+
+    // a newer copy is left alone
+    let handle = ctx.set_contents_expiring("secret".to_owned(), Duration::from_millis(100)).unwrap();
     ctx.set_contents("some string".to_owned()).unwrap();
+    handle.wait();
     assert!(ctx.get_contents().unwrap() == "some string");
+
+    ctx.set_contents_expiring("secret".to_owned(), Duration::from_secs(60)).unwrap().cancel();
+    assert!(ctx.get_contents().unwrap() == "secret");
+}
+
+#[cfg(all(any(unix, windows), not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_set_contents_replaces_other_targets() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_target_contents(formats::PNG, b"not really a png").unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+    assert!(ctx.get_target_contents(formats::PNG).map_or(true, |data| data.is_empty()));
+
+    ctx.set_target_contents(formats::PNG, b"not really a png").unwrap();
+    ctx.add_contents("some string".to_owned()).unwrap();
+    assert!(ctx.get_contents().unwrap() == "some string");
+    assert!(ctx.get_target_contents(formats::PNG).unwrap().starts_with(b"not really a png"));
+}
+
+#[cfg(all(any(unix, windows), not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_text_aliases() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    for &alias in formats::TEXT_ALIASES {
+        ctx.set_target_contents(alias, "some string".as_bytes()).unwrap();
+        assert!(ctx.get_target_contents(alias).unwrap() == b"some string");
+    }
+    ctx.set_target_contents("public.utf8-plain-text", "some string".as_bytes()).unwrap();
+    assert!(ctx.get_contents().unwrap() == "some string");
+    assert!(ctx.set_target_contents("text/plain;charset=utf-8", b"\xff").is_err());
+}
+
+// as left by Windows applications that prefix text with a byte order mark
+#[cfg(all(unix, not(any(target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_leading_bom_is_stripped_from_text() {
+    let _clipboard = lock_system_clipboard();
+    let mut writer = ClipboardContext::new().unwrap();
+    let mut reader = ClipboardContext::new().unwrap();
+    writer.set_target_contents(formats::TEXT, "\u{feff}some string".as_bytes()).unwrap();
+    assert!(reader.get_contents().unwrap() == "some string");
+    assert!(reader.get_target_contents(formats::TEXT).unwrap() == b"some string");
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_contexts_exchange_in_process() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::{X11ClipboardContext, Primary, Clipboard};
+    let mut primary_owner: X11ClipboardContext<Primary> = ClipboardProvider::new().unwrap();
+    let mut clipboard_owner: X11ClipboardContext<Clipboard> = ClipboardProvider::new().unwrap();
+    // each context owns its selections through a window of its own
+    assert!(primary_owner.x11_clipboard().setter.window != clipboard_owner.x11_clipboard().setter.window);
+    primary_owner.set_contents("primary string".to_owned()).unwrap();
+    clipboard_owner.set_contents("clipboard string".to_owned()).unwrap();
+
+    let mut primary_reader: X11ClipboardContext<Primary> = ClipboardProvider::new().unwrap();
+    let mut clipboard_reader: X11ClipboardContext<Clipboard> = ClipboardProvider::new().unwrap();
+    assert!(primary_reader.get_contents().unwrap() == "primary string");
+    assert!(clipboard_reader.get_contents().unwrap() == "clipboard string");
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_incremental_progress() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11ClipboardContext;
+    let mut owner: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    // twice what fits in one request, so the owner has to use INCR
+    let max_request_bytes = owner.x11_clipboard().setter.connection.get_maximum_request_length() as usize * 4;
+    let data = vec![b'x'; max_request_bytes * 2];
+    owner.set_target_contents("rust-clipboard/test", &data).unwrap();
+
+    let mut reader: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    let mut received = Vec::new();
+    let contents = reader.get_target_contents_with_progress("rust-clipboard/test", |len| received.push(len)).unwrap();
+    assert!(contents == data);
+    assert!(!received.is_empty() && received.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_set_new_custom_target() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11ClipboardContext;
+    // a name no other client has interned yet
+    let target = format!("rust-clipboard/new-target-{}", std::process::id());
+    let mut owner: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    owner.set_target_contents(target.as_str(), b"some data").unwrap();
+    let mut reader: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    assert!(reader.get_target_contents(target.as_str()).unwrap() == b"some data");
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_absent_target_returns_fast() {
+    let _clipboard = lock_system_clipboard();
+    use std::time::{Duration, Instant};
+    use x11_clipboard::X11ClipboardContext;
+    let mut owner: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    owner.set_contents("some string".to_owned()).unwrap();
+    let mut reader: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    let start = Instant::now();
+    assert!(reader.get_target_contents("rust-clipboard/absent-target").unwrap().is_empty());
+    assert!(start.elapsed() < Duration::from_millis(500));
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_binary_target_round_trip() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11ClipboardContext;
+    let data: Vec<u8> = (0..=255).chain(vec![0, 0xff, 0xfe, 0]).collect();
+    let mut owner: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    owner.set_target_contents("application/x-rust-clipboard-binary", &data).unwrap();
+    let mut reader: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    assert!(reader.get_target_contents("application/x-rust-clipboard-binary").unwrap() == data);
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_reconnect_keeps_configuration() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11ClipboardContext;
+    let mut ctx: X11ClipboardContext = X11ClipboardContext::with_property("RUST_CLIPBOARD_TEST").unwrap();
+    ctx.reconnect().unwrap();
+    ctx.set_contents("some string".to_owned()).unwrap();
+    assert!(ctx.get_contents().unwrap() == "some string");
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_dual_context_reconnects_both_selections() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11DualContext;
+    let mut ctx = X11DualContext::new().unwrap();
+    // a single selection can't leave the shared connection
+    assert!(ctx.clipboard().reconnect().is_err());
+    ctx.reconnect().unwrap();
+    assert!(ctx.clipboard().x11_clipboard().setter.window == ctx.primary().x11_clipboard().setter.window);
+    ctx.clipboard().set_contents("clipboard string".to_owned()).unwrap();
+    ctx.primary().set_contents("primary string".to_owned()).unwrap();
+    assert!(ctx.clipboard().get_contents().unwrap() == "clipboard string");
+    assert!(ctx.primary().get_contents().unwrap() == "primary string");
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_dual_context_keeps_selections_apart() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11DualContext;
+    let mut owner = X11DualContext::new().unwrap();
+    owner.clipboard().set_contents("clipboard string".to_owned()).unwrap();
+    owner.primary().set_contents("primary string".to_owned()).unwrap();
+
+    let mut reader = X11DualContext::new().unwrap();
+    assert!(reader.clipboard().get_contents().unwrap() == "clipboard string");
+    assert!(reader.primary().get_contents().unwrap() == "primary string");
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_detached_selection_outlives_context() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11ClipboardContext;
+    let mut owner: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    owner.set_contents("detached string".to_owned()).unwrap();
+    let detached = owner.into_detached();
+
+    let mut reader: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    assert!(reader.get_contents().unwrap() == "detached string");
+    detached.stop();
+}
+
+// whether a clipboard manager runs depends on the session
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_request_persistence() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11ClipboardContext;
+    use x11_clipboard_crate::xcb;
+    let mut ctx: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    assert!(ctx.request_persistence().is_err());
+    ctx.set_contents("persisted string".to_owned()).unwrap();
+    let getter = &ctx.x11_clipboard().getter;
+    let manager = getter.get_atom("CLIPBOARD_MANAGER").unwrap();
+    let manager_owner = xcb::get_selection_owner(&getter.connection, manager).get_reply().unwrap().owner();
+    let accepted = ctx.request_persistence().unwrap();
+    // whether a running manager accepts is up to the manager
+    if manager_owner == xcb::NONE {
+        assert!(!accepted);
+    }
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_files_with_effect() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    assert!(ctx.set_files_with_effect(&["relative.txt"], true).is_err());
+    ctx.set_files_with_effect(&["/tmp/a.txt"], true).unwrap();
+    assert!(ctx.get_target_contents(formats::GNOME_FILES).unwrap() == b"cut\nfile:///tmp/a.txt");
+    assert!(ctx.drop_effect().unwrap() == Some(true));
+    ctx.set_files_with_effect(&["/tmp/a.txt"], false).unwrap();
+    assert!(ctx.drop_effect().unwrap() == Some(false));
+    // KDE's marker alone is enough
+    ctx.clear().unwrap();
+    ctx.set_target_contents(formats::KDE_CUT, b"1").unwrap();
+    assert!(ctx.drop_effect().unwrap() == Some(true));
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_get_files_as() {
+    let _clipboard = lock_system_clipboard();
+    use std::path::PathBuf;
+    let mut ctx = ClipboardContext::new().unwrap();
+    let path = PathBuf::from("/tmp/a b/ü.txt");
+    ctx.set_files_with_effect(&[&path], false).unwrap();
+    assert!(ctx.get_files_as(FileFormat::Paths).unwrap() == FileList::Paths(vec![path]));
+    let urls = FileList::Urls(vec!["file:///tmp/a%20b/%C3%BC.txt".to_owned()]);
+    assert!(ctx.get_files_as(FileFormat::Urls).unwrap() == urls);
+    ctx.set_target_contents(formats::URI_LIST, b"# comment\r\nfile://localhost/etc/hosts\r\nhttps://example.com/\r\n").unwrap();
+    assert!(ctx.get_files_as(FileFormat::Paths).unwrap() == FileList::Paths(vec![PathBuf::from("/etc/hosts")]));
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_peek_target() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_target_contents(formats::HTML, b"<b>peek</b>").unwrap();
+    assert!(ctx.peek_target(formats::HTML).unwrap());
+    assert!(!ctx.peek_target(formats::PNG).unwrap());
+    ctx.set_contents("peek".to_owned()).unwrap();
+    assert!(ctx.peek_contents().unwrap());
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_image_preference() {
+    let _clipboard = lock_system_clipboard();
+    use std::collections::HashMap;
+    let mut ctx = ClipboardContext::new().unwrap();
+    let mut images: HashMap<&str, &[u8]> = HashMap::new();
+    images.insert(formats::PNG, b"png");
+    images.insert(formats::BMP, b"bmp");
+    images.insert(formats::TIFF, b"tiff");
+    ctx.set_multiple_targets(images).unwrap();
+    assert!(ctx.get_image_bytes().unwrap() == Some((formats::PNG, b"png".to_vec())));
+    ctx.set_image_preference(&[formats::TIFF, formats::BMP, formats::PNG]);
+    assert!(ctx.get_image_bytes().unwrap() == Some((formats::TIFF, b"tiff".to_vec())));
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_set_text_raw() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    // "café" in Latin-1
+    let latin1 = vec![0x63, 0x61, 0x66, 0xe9];
+    ctx.set_text_raw(latin1.clone()).unwrap();
+    assert!(ctx.get_target_contents("STRING").unwrap() == latin1);
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_get_all_contents() {
+    let _clipboard = lock_system_clipboard();
+    use std::collections::HashMap;
+    let mut owner = ClipboardContext::new().unwrap();
+    let mut targets = HashMap::new();
+    targets.insert(formats::HTML, &b"<b>some string</b>"[..]);
+    targets.insert("rust-clipboard/test", &b"some data"[..]);
+    owner.set_multiple_targets(targets).unwrap();
+    let mut reader = ClipboardContext::new().unwrap();
+    let contents = reader.get_all_contents().unwrap();
+    assert!(contents.contains(&(formats::HTML.to_owned(), b"<b>some string</b>".to_vec())));
+    assert!(contents.contains(&("rust-clipboard/test".to_owned(), b"some data".to_vec())));
+    assert!(!contents.iter().any(|(target, _)| target == "TARGETS"));
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_last_change_time() {
+    let _clipboard = lock_system_clipboard();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.set_contents("timestamped".to_owned()).unwrap();
+    // x11-clipboard owns the selection without a timestamp of its own
+    let mut reader = ClipboardContext::new().unwrap();
+    assert!(reader.last_change_time().unwrap().is_none());
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_last_change_time_of_timestamped_owner() {
+    let _clipboard = lock_system_clipboard();
+    use std::time::{Duration, SystemTime};
+    use x11_clipboard_crate::{xcb, Context};
+    // an owner made by hand, since x11-clipboard doesn't answer TIMESTAMP
+    let owner = Context::new(None).unwrap();
+    let timestamp = owner.get_atom("TIMESTAMP").unwrap();
+    // appending nothing to a property reports the server time to own the selection with
+    let empty: &[u8] = &[];
+    xcb::change_property(&owner.connection, xcb::PROP_MODE_APPEND as u8, owner.window, xcb::ATOM_WM_NAME, xcb::ATOM_STRING, 8, empty);
+    owner.connection.flush();
+    let acquired = loop {
+        let event = owner.connection.wait_for_event().unwrap();
+        if event.response_type() & !0x80 == xcb::PROPERTY_NOTIFY {
+            break unsafe { xcb::cast_event::<xcb::PropertyNotifyEvent>(&event) }.time();
+        }
+    };
+    xcb::set_selection_owner(&owner.connection, owner.window, owner.atoms.clipboard, acquired);
+    owner.connection.flush();
+    // answers TIMESTAMP and refuses anything else until it loses the selection
+    let answering = std::thread::spawn(move || {
+        while let Some(event) = owner.connection.wait_for_event() {
+            match event.response_type() & !0x80 {
+                xcb::SELECTION_REQUEST => {
+                    let request = unsafe { xcb::cast_event::<xcb::SelectionRequestEvent>(&event) };
+                    let property = if request.target() == timestamp {
+                        xcb::change_property(&owner.connection, xcb::PROP_MODE_REPLACE as u8, request.requestor(),
+                                             request.property(), xcb::ATOM_INTEGER, 32, &[acquired]);
+                        request.property()
+                    } else {
+                        xcb::NONE
+                    };
+                    let notify = xcb::SelectionNotifyEvent::new(request.time(), request.requestor(), request.selection(),
+                                                                request.target(), property);
+                    xcb::send_event(&owner.connection, false, request.requestor(), 0, &notify);
+                    owner.connection.flush();
+                }
+                xcb::SELECTION_CLEAR => return,
+                _ => (),
+            }
+        }
+    });
+
+    let mut ctx = ClipboardContext::new().unwrap();
+    let changed = ctx.last_change_time().unwrap().expect("the owner reported when it acquired CLIPBOARD");
+    let distance = match SystemTime::now().duration_since(changed) {
+        Ok(age) => age,
+        Err(ahead) => ahead.duration(),
+    };
+    assert!(distance < Duration::from_secs(5));
+    // taking the selection over lets the owner finish
+    ctx.set_contents("some string".to_owned()).unwrap();
+    answering.join().unwrap();
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_clear_leaves_other_owners_alone() {
+    let _clipboard = lock_system_clipboard();
+    let mut owner = ClipboardContext::new().unwrap();
+    owner.set_contents("other owner".to_owned()).unwrap();
+    let mut ctx = ClipboardContext::new().unwrap();
+    ctx.clear().unwrap();
+    ctx.clear_all().unwrap();
+    assert!(ctx.get_contents().unwrap() == "other owner");
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_clear_all() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11DualContext;
+    let mut owner = X11DualContext::new().unwrap();
+    owner.clipboard().set_contents("clipboard".to_owned()).unwrap();
+    owner.primary().set_contents("primary".to_owned()).unwrap();
+    owner.clipboard().clear_all().unwrap();
+    let mut reader = X11DualContext::new().unwrap();
+    assert!(reader.clipboard().get_contents().map_or(true, |contents| contents.is_empty()));
+    assert!(reader.primary().get_contents().map_or(true, |contents| contents.is_empty()));
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_detached_expiring_contents() {
+    let _clipboard = lock_system_clipboard();
+    use std::time::Duration;
+    use x11_clipboard::X11ClipboardContext;
+    let owner: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    let handle = owner.set_contents_detached_expiring("secret".to_owned(), Duration::from_millis(500)).unwrap();
+    let mut reader: X11ClipboardContext = ClipboardProvider::new().unwrap();
+    assert!(reader.get_contents().unwrap() == "secret");
+    handle.wait();
+    assert!(reader.get_contents().map_or(true, |contents| contents.is_empty()));
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_primary_fallback() {
+    let _clipboard = lock_system_clipboard();
+    use x11_clipboard::X11DualContext;
+    let mut owner = X11DualContext::new().unwrap();
+    owner.clipboard().clear().unwrap();
+    owner.primary().set_contents("primary string".to_owned()).unwrap();
+
+    let mut reader = X11DualContext::new().unwrap();
+    assert!(reader.clipboard().get_contents_with_primary_fallback().unwrap() == "primary string");
+    owner.clipboard().set_contents("clipboard string".to_owned()).unwrap();
+    assert!(reader.clipboard().get_contents_with_primary_fallback().unwrap() == "clipboard string");
+}
+
+#[cfg(all(unix, not(any(target_os="macos", target_os="android", target_os="emscripten")), not(feature = "nop")))]
+#[test]
+fn test_x11_copy_selection() {
+    let _clipboard = lock_system_clipboard();
+    use std::collections::HashMap;
+    use x11_clipboard::{Clipboard, Primary, X11DualContext};
+    let mut owner = X11DualContext::new().unwrap();
+    let mut targets = HashMap::new();
+    targets.insert("text/html", &b"<b>selected</b>"[..]);
+    targets.insert("UTF8_STRING", &b"selected"[..]);
+    owner.primary().set_multiple_targets(targets).unwrap();
+
+    let mut ctx = X11DualContext::new().unwrap();
+    ctx.copy_selection::<Primary, Clipboard>().unwrap();
+    assert!(ctx.clipboard().get_contents().unwrap() == "selected");
+    assert!(ctx.clipboard().get_target_contents("text/html").unwrap() == b"<b>selected</b>");
 }
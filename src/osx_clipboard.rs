@@ -15,32 +15,198 @@ limitations under the License.
 */
 
 use common::*;
+use formats;
 use objc::runtime::{Object, Class};
-use objc_foundation::{INSArray, INSString, INSObject};
-use objc_foundation::{NSArray, NSDictionary, NSString, NSObject};
+use objc_foundation::{INSArray, INSData, INSString, INSObject};
+use objc_foundation::{NSArray, NSData, NSDictionary, NSString, NSObject};
 use objc_id::{Id, Owned};
 use std::error::Error;
 use std::mem::transmute;
+use std::path::PathBuf;
+use std::ptr;
 
+/// Context for one pasteboard. It is deliberately not `Clone`, create
+/// another context with `new` or `with_name` instead.
+///
+/// The context holds an Objective-C reference and isn't `Send`, so create
+/// one on every thread that needs it. Only `NSPasteboard` and Foundation
+/// objects are used, and images travel as encoded `NSData` without an
+/// `NSImage` being created, so no method needs to run on the main thread.
 pub struct OSXClipboardContext {
     pasteboard: Id<Object>,
+    max_read_bytes: usize,
+    image_preference: &'static [&'static str],
 }
 
+// how often snapshot_atomic reads the pasteboard before giving up
+const SNAPSHOT_ATTEMPTS: usize = 3;
+
 // required to bring NSPasteboard into the path of the class-resolver
 #[link(name = "AppKit", kind = "framework")]
 extern "C" {}
 
+impl OSXClipboardContext {
+    /// Create a context for the named pasteboard instead of the general one,
+    /// e.g. `"Apple CFPasteboard find"` (`NSFindPboard`), `"Apple CFPasteboard drag"`
+    /// (`NSDragPboard`) or an application-specific name.
+    pub fn with_name(name: &str) -> Result<OSXClipboardContext, Box<dyn Error + Send + Sync>> {
+        let cls = try!(Class::get("NSPasteboard").ok_or(err("Class::get(\"NSPasteboard\")")));
+        let name = NSString::from_str(name);
+        let pasteboard: *mut Object = unsafe { msg_send![cls, pasteboardWithName:&*name] };
+        if pasteboard.is_null() {
+            return Err(err("NSPasteboard#pasteboardWithName: returned null"));
+        }
+        let pasteboard: Id<Object> = unsafe { Id::from_ptr(pasteboard) };
+        Ok(OSXClipboardContext {
+            pasteboard: pasteboard,
+            max_read_bytes: DEFAULT_MAX_READ_BYTES,
+            image_preference: formats::IMAGES,
+        })
+    }
+
+    /// Refuse to read targets larger than `max_read_bytes` with an error,
+    /// `DEFAULT_MAX_READ_BYTES` by default.
+    ///
+    /// The owner has already rendered the data by the time its size is
+    /// known, so this only saves copying it into the process' own buffer.
+    pub fn set_max_read_bytes(&mut self, max_read_bytes: usize) {
+        self.max_read_bytes = max_read_bytes;
+    }
+
+    /// Set the image targets `get_image_bytes` tries, most preferred first,
+    /// e.g. `&[formats::PNG, formats::JPEG]` to skip uncompressed bitmaps.
+    /// `get_image` keeps the platform's order.
+    pub fn set_image_preference(&mut self, targets: &'static [&'static str]) {
+        self.image_preference = targets;
+    }
+
+    /// Access the underlying `NSPasteboard` for AppKit operations the trait
+    /// doesn't cover, such as `writeObjects:` with custom pasteboard items.
+    pub fn pasteboard(&self) -> &Object {
+        &self.pasteboard
+    }
+
+    // The pasteboard's own dataForType: and types only look at the first
+    // item, while a later item may offer the type among its representations
+    unsafe fn item_data_for_type(&self, target: &NSString) -> *mut NSData {
+        let items: *mut Object = msg_send![self.pasteboard, pasteboardItems];
+        if items.is_null() {
+            return ptr::null_mut();
+        }
+        let count: usize = msg_send![items, count];
+        for i in 0..count {
+            let item: *mut Object = msg_send![items, objectAtIndex:i];
+            let data: *mut NSData = msg_send![item, dataForType:target];
+            if !data.is_null() {
+                return data;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    /// Read every type currently on the pasteboard along with its data,
+    /// skipping types whose data can't be read.
+    pub fn get_all_contents(&mut self) -> Result<Vec<TargetData>, Box<dyn Error + Send + Sync>> {
+        let mut contents = Vec::new();
+        for target in self.types()? {
+            if let Ok(data) = self.get_target_contents(target.as_str()) {
+                contents.push((target, data));
+            }
+        }
+        Ok(contents)
+    }
+
+    /// Read the files on the pasteboard, one `public.file-url` per item,
+    /// either as paths or as `file://` urls, or an empty list when there are
+    /// none.
+    ///
+    /// File reference urls (`file:///.file/id=...`) are resolved to the
+    /// file they refer to.
+    pub fn get_files_as(&mut self, format: FileFormat) -> Result<FileList, Box<dyn Error + Send + Sync>> {
+        let target = NSString::from_str(formats::URI_LIST);
+        let mut files = Vec::new();
+        unsafe {
+            let items: *mut Object = msg_send![self.pasteboard, pasteboardItems];
+            let count: usize = if items.is_null() { 0 } else { msg_send![items, count] };
+            for i in 0..count {
+                let item: *mut Object = msg_send![items, objectAtIndex:i];
+                let string: *mut NSString = msg_send![item, stringForType:&*target];
+                if string.is_null() {
+                    continue;
+                }
+                let url: *mut Object = msg_send![class("NSURL"), URLWithString:string];
+                if url.is_null() {
+                    continue;
+                }
+                let url: *mut Object = msg_send![url, filePathURL];
+                if url.is_null() {
+                    continue;
+                }
+                let file: *mut NSString = match format {
+                    FileFormat::Paths => msg_send![url, path],
+                    FileFormat::Urls => msg_send![url, absoluteString],
+                };
+                files.push((*file).as_str().to_owned());
+            }
+        }
+        Ok(match format {
+            FileFormat::Paths => FileList::Paths(files.into_iter().map(PathBuf::from).collect()),
+            FileFormat::Urls => FileList::Urls(files),
+        })
+    }
+
+    // the pasteboard's types followed by those only later items offer
+    fn types(&self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let mut names: Vec<String> = Vec::new();
+        unsafe {
+            let types: *mut NSArray<NSString> = msg_send![self.pasteboard, types];
+            if types.is_null() {
+                return Err(err("NSPasteboard#types returned null"));
+            }
+            let mut arrays = vec![types];
+            let items: *mut Object = msg_send![self.pasteboard, pasteboardItems];
+            let count: usize = if items.is_null() { 0 } else { msg_send![items, count] };
+            for i in 0..count {
+                let item: *mut Object = msg_send![items, objectAtIndex:i];
+                let types: *mut NSArray<NSString> = msg_send![item, types];
+                if !types.is_null() {
+                    arrays.push(types);
+                }
+            }
+            for types in arrays {
+                for target in (*types).to_vec() {
+                    if !names.iter().any(|name| name == target.as_str()) {
+                        names.push(target.as_str().to_owned());
+                    }
+                }
+            }
+        }
+        Ok(names)
+    }
+}
+
+// dynamic UTIs and legacy names only alias types that are listed as well
+fn is_data_type(target: &str) -> bool {
+    !(target.starts_with("dyn.")
+        || (target.starts_with("NS") && target.ends_with("PboardType"))
+        || target.starts_with("CorePasteboardFlavorType"))
+}
+
 impl ClipboardProvider for OSXClipboardContext {
-    fn new() -> Result<OSXClipboardContext, Box<dyn Error>> {
+    fn new() -> Result<OSXClipboardContext, Box<dyn Error + Send + Sync>> {
         let cls = try!(Class::get("NSPasteboard").ok_or(err("Class::get(\"NSPasteboard\")")));
         let pasteboard: *mut Object = unsafe { msg_send![cls, generalPasteboard] };
         if pasteboard.is_null() {
             return Err(err("NSPasteboard#generalPasteboard returned null"));
         }
         let pasteboard: Id<Object> = unsafe { Id::from_ptr(pasteboard) };
-        Ok(OSXClipboardContext { pasteboard: pasteboard })
+        Ok(OSXClipboardContext {
+            pasteboard: pasteboard,
+            max_read_bytes: DEFAULT_MAX_READ_BYTES,
+            image_preference: formats::IMAGES,
+        })
     }
-    fn get_contents(&mut self) -> Result<String, Box<dyn Error>> {
+    fn get_contents(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
         let string_class: Id<NSObject> = {
             let cls: Id<Class> = unsafe { Id::from_ptr(class("NSString")) };
             unsafe { transmute(cls) }
@@ -58,18 +224,138 @@ impl ClipboardProvider for OSXClipboardContext {
         if string_array.count() == 0 {
             Err(err("pasteboard#readObjectsForClasses:options: returned empty"))
         } else {
-            Ok(string_array[0].as_str().to_owned())
+            Ok(strip_bom(string_array[0].as_str().to_owned()))
         }
     }
-    fn set_contents(&mut self, data: String) -> Result<(), Box<dyn Error>> {
+    fn set_contents(&mut self, data: String) -> Result<(), Box<dyn Error + Send + Sync>> {
         let string_array = NSArray::from_vec(vec![NSString::from_str(&data)]);
         let _: usize = unsafe { msg_send![self.pasteboard, clearContents] };
         let success: bool = unsafe { msg_send![self.pasteboard, writeObjects:string_array] };
-        return if success {
+        if success {
             Ok(())
         } else {
             Err(err("NSPasteboard#writeObjects: returned false"))
+        }
+    }
+    fn backend_name(&self) -> &'static str {
+        "osx"
+    }
+
+    fn image_preference(&self) -> &'static [&'static str] {
+        self.image_preference
+    }
+
+    fn clear(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let _: usize = unsafe { msg_send![self.pasteboard, clearContents] };
+        Ok(())
+    }
+
+    // dataForType: synchronously asks the owner to render promised data
+    // (NSPasteboardItemDataProvider, e.g. Preview's public.tiff), so promised
+    // types read like any other. File promises are not data and can't be
+    // resolved this way.
+    fn get_target_contents(&mut self, target: impl ToString) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let name = formats::resolve_text_alias(&target.to_string()).to_owned();
+        let target = NSString::from_str(&name);
+        let data: Id<NSData> = unsafe {
+            let mut obj: *mut NSData = msg_send![self.pasteboard, dataForType:&*target];
+            if obj.is_null() {
+                obj = self.item_data_for_type(&target);
+            }
+            if obj.is_null() {
+                let types = NSArray::from_vec(vec![NSString::from_str(target.as_str())]);
+                let available: *mut NSString = msg_send![self.pasteboard, availableTypeFromArray:&*types];
+                return Err(if available.is_null() {
+                    err("NSPasteboard#dataForType: returned null")
+                } else {
+                    err("NSPasteboard#dataForType: returned null, the owner failed to provide promised data")
+                });
+            }
+            Id::from_ptr(obj)
+        };
+        if data.len() > self.max_read_bytes {
+            return Err(too_large(self.max_read_bytes));
+        }
+        Ok(strip_text_bom(&name, data.bytes().to_vec()))
+    }
+
+    // there is no way to lock the pasteboard, but every write bumps changeCount
+    // data providers are only asked for data, not for the types they offer
+    fn peek_target(&mut self, target: impl ToString) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let target = target.to_string();
+        let target = formats::resolve_text_alias(&target);
+        Ok(self.types()?.iter().any(|offered| offered == target))
+    }
+
+    fn list_data_targets(&mut self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        Ok(self.types()?.into_iter().filter(|target| is_data_type(target)).collect())
+    }
+
+    fn snapshot_atomic(&mut self) -> Result<Vec<TargetData>, Box<dyn Error + Send + Sync>> {
+        for _ in 0..SNAPSHOT_ATTEMPTS {
+            let before: isize = unsafe { msg_send![self.pasteboard, changeCount] };
+            let contents = self.get_all_contents()?;
+            let after: isize = unsafe { msg_send![self.pasteboard, changeCount] };
+            if before == after {
+                return Ok(contents);
+            }
+        }
+        Err(err("the pasteboard kept changing while taking a snapshot"))
+    }
+
+    fn get_target_contents_opt(&mut self, target: impl ToString) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+        let target = formats::resolve_text_alias(&target.to_string()).to_owned();
+        let types = NSArray::from_vec(vec![NSString::from_str(&target)]);
+        let available: *mut NSString = unsafe { msg_send![self.pasteboard, availableTypeFromArray:&*types] };
+        if available.is_null() && unsafe { self.item_data_for_type(&NSString::from_str(&target)) }.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(self.get_target_contents(target)?))
+    }
+
+    // Adds the target to the existing pasteboard contents instead of clearing them, so
+    // several formats can be built up incrementally. Since nothing is cleared the
+    // pasteboard's changeCount is not bumped; use set_multiple_targets to replace
+    // the contents and notify observers.
+    fn set_target_contents(&mut self, target: impl ToString, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let target = formats::resolve_text_alias(&target.to_string()).to_owned();
+        check_text_encoding(&target, data)?;
+        let types = NSArray::from_vec(vec![NSString::from_str(&target)]);
+        let data = NSData::with_bytes(data);
+        let _: isize = unsafe { msg_send![self.pasteboard, addTypes:&*types owner:ptr::null_mut::<Object>()] };
+        let success: bool = unsafe {
+            msg_send![self.pasteboard, setData:&*data forType:&*NSString::from_str(&target)]
         };
+        if success {
+            Ok(())
+        } else {
+            Err(err("NSPasteboard#setData:forType: returned false"))
+        }
+    }
+
+    fn set_multiple_targets(&mut self, targets: TargetMap<impl ToString>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if targets.is_empty() {
+            return self.clear();
+        }
+        let targets: Vec<(String, &[u8])> = targets.into_iter()
+            .map(|(key, value)| (formats::resolve_text_alias(&key.to_string()).to_owned(), value))
+            .collect();
+        for (key, value) in &targets {
+            check_text_encoding(key, value)?;
+        }
+        let types = NSArray::from_vec(targets.iter().map(|(key, _)| NSString::from_str(key)).collect());
+        // declareTypes:owner: clears the pasteboard itself, bumping changeCount once
+        let _: isize = unsafe { msg_send![self.pasteboard, declareTypes:&*types owner:ptr::null_mut::<Object>()] };
+        for (key, value) in targets {
+            let data = NSData::with_bytes(value);
+            let success: bool = unsafe {
+                msg_send![self.pasteboard, setData:&*data forType:&*NSString::from_str(&key)]
+            };
+            if !success {
+                return Err(err("NSPasteboard#setData:forType: returned false"));
+            }
+        }
+        Ok(())
     }
 }
 
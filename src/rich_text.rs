@@ -0,0 +1,265 @@
+/*
+Copyright 2016 Avraham Weinstock
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Best-effort conversion of rich text targets to plain text, in the spirit
+//! of what a terminal does when only rich text is offered. Markup that
+//! can't be interpreted is dropped rather than reported.
+
+// tags after which a line break is inserted
+const BLOCK_TAGS: &[&str] = &["br", "p", "div", "li", "tr", "h1", "h2", "h3", "h4", "h5", "h6"];
+
+// RTF destinations whose content is not part of the text
+const RTF_SKIPPED: &[&str] = &["fonttbl", "colortbl", "stylesheet", "info", "pict", "header", "footer"];
+
+/// Strip tags from HTML, decoding the common entities
+pub fn html_to_text(html: &str) -> String {
+    let html = html.trim_start_matches('\u{feff}');
+    // Windows prefixes "HTML Format" with a description header
+    let html = if html.starts_with("Version:") {
+        html.find('<').map_or("", |start| &html[start..])
+    } else {
+        html
+    };
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find(&['<', '&'][..]) {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with('&') {
+            let (decoded, len) = decode_entity(rest);
+            text.push_str(&decoded);
+            rest = &rest[len..];
+            continue;
+        }
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let closing = rest[1..].starts_with('/');
+        let tag = rest[1..end].trim_start_matches('/').trim_end_matches('/');
+        let name = tag.split_whitespace().next().unwrap_or("").to_lowercase();
+        rest = &rest[end + 1..];
+        if !closing && (name == "script" || name == "style") {
+            // skip to the end of the element
+            let close = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(close) => &rest[close..],
+                None => "",
+            };
+        } else if BLOCK_TAGS.contains(&name.as_str()) && !text.ends_with('\n') && !text.is_empty() {
+            text.push('\n');
+        }
+    }
+    text.push_str(rest);
+    text.trim().to_owned()
+}
+
+// returns the decoded entity and how many bytes it took, or the ampersand
+// itself when it doesn't start a known entity
+fn decode_entity(s: &str) -> (String, usize) {
+    let end = match s.find(';') {
+        Some(end) if end <= 10 => end,
+        _ => return ("&".to_owned(), 1),
+    };
+    let decoded = match &s[1..end] {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        entity if entity.starts_with("#x") || entity.starts_with("#X") => {
+            u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32)
+        }
+        entity if entity.starts_with('#') => {
+            entity[1..].parse().ok().and_then(::std::char::from_u32)
+        }
+        _ => None,
+    };
+    match decoded {
+        Some(c) => (c.to_string(), end + 1),
+        None => ("&".to_owned(), 1),
+    }
+}
+
+/// Strip control words and groups from RTF, keeping paragraph breaks
+pub fn rtf_to_text(rtf: &str) -> String {
+    let mut text = String::new();
+    // depth at which an ignored destination started
+    let mut skip_from: Option<usize> = None;
+    let mut depth = 0;
+    // fallback units following \uN, scoped to the group like any property
+    let mut uc = 1;
+    let mut outer_uc = Vec::new();
+    let mut chars = rtf.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                depth += 1;
+                outer_uc.push(uc);
+            }
+            '}' => {
+                if skip_from == Some(depth) {
+                    skip_from = None;
+                }
+                depth = depth.saturating_sub(1);
+                uc = outer_uc.pop().unwrap_or(1);
+            }
+            '\\' => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_alphabetic() {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if word.is_empty() {
+                    // control symbol
+                    match chars.next() {
+                        Some('*') if skip_from.is_none() => skip_from = Some(depth),
+                        Some('\'') => {
+                            let hex: String = chars.by_ref().take(2).collect();
+                            if let Ok(byte) = u8::from_str_radix(&hex, 16) {
+                                if skip_from.is_none() {
+                                    // assume Windows-1252, the default \ansicpg
+                                    text.push(windows_1252(byte));
+                                }
+                            }
+                        }
+                        Some(c @ '\\') | Some(c @ '{') | Some(c @ '}') if skip_from.is_none() => text.push(c),
+                        _ => (),
+                    }
+                    continue;
+                }
+                let mut param = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_digit() || (c == '-' && param.is_empty())) {
+                        break;
+                    }
+                    param.push(c);
+                    chars.next();
+                }
+                // a single space delimits the control word
+                if chars.peek() == Some(&' ') {
+                    chars.next();
+                }
+                if RTF_SKIPPED.contains(&word.as_str()) && skip_from.is_none() {
+                    skip_from = Some(depth);
+                }
+                if skip_from.is_some() {
+                    continue;
+                }
+                match word.as_str() {
+                    "par" | "line" => text.push('\n'),
+                    "tab" => text.push('\t'),
+                    "uc" => uc = param.parse().unwrap_or(1),
+                    "u" => {
+                        let code: i32 = param.parse().unwrap_or(0);
+                        if let Some(c) = ::std::char::from_u32(code as u16 as u32) {
+                            text.push(c);
+                        }
+                        // skip the fallback following \uN, where an escape
+                        // such as \'e9 or a control word counts as one unit
+                        for _ in 0..uc {
+                            match chars.peek() {
+                                None | Some('{') | Some('}') => break,
+                                Some('\\') => {
+                                    chars.next();
+                                    match chars.next() {
+                                        Some('\'') => {
+                                            chars.next();
+                                            chars.next();
+                                        }
+                                        Some(c) if c.is_ascii_alphabetic() => {
+                                            while chars.peek().is_some_and(|&c| c.is_ascii_alphanumeric() || c == '-') {
+                                                chars.next();
+                                            }
+                                            if chars.peek() == Some(&' ') {
+                                                chars.next();
+                                            }
+                                        }
+                                        _ => (),
+                                    }
+                                }
+                                Some(_) => {
+                                    chars.next();
+                                }
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            '\r' | '\n' => (),
+            c => if skip_from.is_none() { text.push(c) },
+        }
+    }
+    text.trim().to_owned()
+}
+
+// the 0x80-0x9F range is where Windows-1252 differs from Latin-1; the bytes
+// it leaves undefined are kept as C1 controls, like Windows does
+fn windows_1252(byte: u8) -> char {
+    match byte {
+        0x80 => '\u{20ac}',
+        0x82 => '\u{201a}',
+        0x83 => '\u{192}',
+        0x84 => '\u{201e}',
+        0x85 => '\u{2026}',
+        0x86 => '\u{2020}',
+        0x87 => '\u{2021}',
+        0x88 => '\u{2c6}',
+        0x89 => '\u{2030}',
+        0x8a => '\u{160}',
+        0x8b => '\u{2039}',
+        0x8c => '\u{152}',
+        0x8e => '\u{17d}',
+        0x91 => '\u{2018}',
+        0x92 => '\u{2019}',
+        0x93 => '\u{201c}',
+        0x94 => '\u{201d}',
+        0x95 => '\u{2022}',
+        0x96 => '\u{2013}',
+        0x97 => '\u{2014}',
+        0x98 => '\u{2dc}',
+        0x99 => '\u{2122}',
+        0x9a => '\u{161}',
+        0x9b => '\u{203a}',
+        0x9c => '\u{153}',
+        0x9e => '\u{17e}',
+        0x9f => '\u{178}',
+        byte => byte as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtf_to_text() {
+        assert!(rtf_to_text(r"{\rtf1{\fonttbl{\f0 Arial;}}\f0 some \'e9\par string}") == "some \u{e9}\nstring");
+        assert!(rtf_to_text(r"{\rtf1 \'93some\'94 \'80\'97string}") == "\u{201c}some\u{201d} \u{20ac}\u{2014}string");
+    }
+
+    #[test]
+    fn test_rtf_unicode_fallback() {
+        assert!(rtf_to_text(r"{\rtf1 some \u233\'e9 string}") == "some \u{e9} string");
+        assert!(rtf_to_text(r"{\rtf1 \uc2 some \u8364\'80\'80 string}") == "some \u{20ac} string");
+        assert!(rtf_to_text(r"{\rtf1 {\uc0 \u8364}\u233 e string}") == "\u{20ac}\u{e9} string");
+    }
+}
@@ -0,0 +1,164 @@
+/*
+Copyright 2016 Avraham Weinstock
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+
+use common::{ClipboardProvider, TargetData, TargetMap};
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::SystemTime;
+
+/// Clipboard that writes to two other clipboards at once, e.g. to keep the
+/// X11 and another session's clipboard in sync.
+///
+/// Writes go to both clipboards, reads return the first non-empty result.
+/// Nest contexts to fan out to more than two clipboards.
+pub struct TeeClipboardContext<A, B>
+where
+    A: ClipboardProvider,
+    B: ClipboardProvider,
+{
+    first: A,
+    second: B,
+}
+
+impl<A, B> TeeClipboardContext<A, B>
+where
+    A: ClipboardProvider,
+    B: ClipboardProvider,
+{
+    /// Combine two existing contexts
+    pub fn from_contexts(first: A, second: B) -> TeeClipboardContext<A, B> {
+        TeeClipboardContext { first, second }
+    }
+}
+
+impl<A, B> ClipboardProvider for TeeClipboardContext<A, B>
+where
+    A: ClipboardProvider,
+    B: ClipboardProvider,
+{
+    fn new() -> Result<TeeClipboardContext<A, B>, Box<dyn Error + Send + Sync>> {
+        Ok(TeeClipboardContext { first: A::new()?, second: B::new()? })
+    }
+
+    fn get_contents(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        match self.first.get_contents() {
+            Ok(ref contents) if contents.is_empty() => self.second.get_contents(),
+            Err(_) => self.second.get_contents(),
+            result => result,
+        }
+    }
+
+    fn set_contents(&mut self, data: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.first.set_contents(data.clone())?;
+        self.second.set_contents(data)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "tee"
+    }
+
+    fn image_preference(&self) -> &'static [&'static str] {
+        self.first.image_preference()
+    }
+
+    fn set_text_raw(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.first.set_text_raw(data.clone())?;
+        self.second.set_text_raw(data)
+    }
+
+    fn clear(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.first.clear()?;
+        self.second.clear()
+    }
+
+    fn clear_all(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.first.clear_all()?;
+        self.second.clear_all()
+    }
+
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.first.reconnect()?;
+        self.second.reconnect()
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+
+    fn get_target_contents(&mut self, target: impl ToString) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let target = target.to_string();
+        match self.first.get_target_contents(target.as_str()) {
+            Ok(ref contents) if contents.is_empty() => self.second.get_target_contents(target),
+            Err(_) => self.second.get_target_contents(target),
+            result => result,
+        }
+    }
+
+    fn get_target_contents_opt(&mut self, target: impl ToString) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+        let target = target.to_string();
+        match self.first.get_target_contents_opt(target.as_str()) {
+            Ok(None) | Err(_) => self.second.get_target_contents_opt(target),
+            result => result,
+        }
+    }
+
+    fn list_data_targets(&mut self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        match self.first.list_data_targets() {
+            Ok(ref targets) if targets.is_empty() => self.second.list_data_targets(),
+            Err(_) => self.second.list_data_targets(),
+            result => result,
+        }
+    }
+
+    fn last_change_time(&mut self) -> Result<Option<SystemTime>, Box<dyn Error + Send + Sync>> {
+        match self.first.last_change_time() {
+            Ok(None) | Err(_) => self.second.last_change_time(),
+            result => result,
+        }
+    }
+
+    fn peek_target(&mut self, target: impl ToString) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let target = target.to_string();
+        match self.first.peek_target(target.as_str()) {
+            Ok(false) | Err(_) => self.second.peek_target(target),
+            result => result,
+        }
+    }
+
+    fn snapshot_atomic(&mut self) -> Result<Vec<TargetData>, Box<dyn Error + Send + Sync>> {
+        match self.first.snapshot_atomic() {
+            Ok(ref contents) if contents.is_empty() => self.second.snapshot_atomic(),
+            Err(_) => self.second.snapshot_atomic(),
+            result => result,
+        }
+    }
+
+    fn set_target_contents(&mut self, target: impl ToString, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let target = target.to_string();
+        self.first.set_target_contents(target.as_str(), data)?;
+        self.second.set_target_contents(target, data)
+    }
+
+    fn set_multiple_targets(&mut self, targets: TargetMap<impl ToString>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let targets: HashMap<String, &[u8]> = targets.into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        self.first.set_multiple_targets(targets.clone())?;
+        self.second.set_multiple_targets(targets)
+    }
+}
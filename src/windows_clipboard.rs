@@ -14,21 +14,670 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use clipboard_win::{get_clipboard_string, set_clipboard_string};
+use clipboard_win::{register_format, Clipboard};
+use clipboard_win::formats::{CF_DIB, CF_DIBV5, CF_HDROP, CF_LOCALE, CF_OEMTEXT, CF_TEXT, CF_UNICODETEXT};
+use winapi::shared::basetsd::SIZE_T;
+use winapi::um::winbase::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+use winapi::um::winuser::SetClipboardData;
 
-use common::ClipboardProvider;
+use common::{percent_encode, strip_bom, too_large, ClipboardProvider, FileFormat, FileList, TargetData, TargetMap, DEFAULT_MAX_READ_BYTES};
+use formats;
+use std::borrow::Cow;
 use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ffi::OsString;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::str;
+use std::thread;
+use std::time::Duration;
 
-pub struct WindowsClipboardContext;
+// size of the DROPFILES header preceding the path list in CF_HDROP data
+const DROPFILES_SIZE: u32 = 20;
+
+const ERROR_NOT_FOUND: i32 = 1168;
+
+const DROPEFFECT_COPY: u32 = 1;
+const DROPEFFECT_MOVE: u32 = 2;
+
+/// How many times opening the clipboard is attempted by default
+pub const DEFAULT_ATTEMPTS: u32 = 10;
+/// Delay between two attempts at opening the clipboard by default
+pub const DEFAULT_ATTEMPT_DELAY: Duration = Duration::from_millis(10);
+
+/// How writes react when another application holds the clipboard open, e.g.
+/// while a remote desktop session redirects it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Retry opening the clipboard as configured by `set_retries`, the default
+    Retry,
+    /// Give up after the first attempt, for latency-sensitive callers
+    FailFast,
+}
+
+/// Error of a write that couldn't open the clipboard, which another
+/// application held open for every attempt
+#[derive(Clone, Copy, Debug)]
+pub struct ClipboardBusy {
+    /// Policy the write was made with
+    pub policy: WritePolicy,
+    /// How often opening the clipboard was attempted
+    pub attempts: u32,
+}
+
+impl fmt::Display for ClipboardBusy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.policy {
+            WritePolicy::FailFast => write!(f, "the clipboard is held open by another application"),
+            WritePolicy::Retry => write!(f, "the clipboard is held open by another application, gave up after {} attempts", self.attempts),
+        }
+    }
+}
+
+impl Error for ClipboardBusy {}
+
+/// Context holding nothing but the retry settings; the clipboard is opened
+/// anew for every operation, so copies can be handed to other threads freely.
+#[derive(Clone, Copy, Debug)]
+pub struct WindowsClipboardContext {
+    attempts: u32,
+    attempt_delay: Duration,
+    max_read_bytes: usize,
+    write_policy: WritePolicy,
+    image_preference: &'static [&'static str],
+}
+
+impl WindowsClipboardContext {
+    /// Configure how often opening the clipboard is attempted and how long to
+    /// wait in between, `DEFAULT_ATTEMPTS` and `DEFAULT_ATTEMPT_DELAY` by default.
+    ///
+    /// Other applications briefly hold the clipboard open while using it; under
+    /// heavy contention (RDP, screen sharing) more attempts avoid spurious failures.
+    pub fn set_retries(&mut self, attempts: u32, attempt_delay: Duration) {
+        self.attempts = attempts;
+        self.attempt_delay = attempt_delay;
+    }
+
+    /// Choose whether writes retry opening a busy clipboard, `WritePolicy::Retry`
+    /// by default. Writes that can't open it fail with `ClipboardBusy`.
+    pub fn set_write_policy(&mut self, write_policy: WritePolicy) {
+        self.write_policy = write_policy;
+    }
+
+    /// Refuse to read targets larger than `max_read_bytes` with an error,
+    /// `DEFAULT_MAX_READ_BYTES` by default. The size is checked before
+    /// anything is allocated.
+    pub fn set_max_read_bytes(&mut self, max_read_bytes: usize) {
+        self.max_read_bytes = max_read_bytes;
+    }
+
+    /// Set the image targets `get_image_bytes` tries, most preferred first,
+    /// e.g. `&[formats::PNG, formats::JPEG]` to skip uncompressed bitmaps.
+    /// `get_image` keeps the platform's order.
+    pub fn set_image_preference(&mut self, targets: &'static [&'static str]) {
+        self.image_preference = targets;
+    }
+
+    // the data can't change while the clipboard is held open
+    fn check_size(&self, clipboard: &Clipboard, format: u32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match clipboard.size(format) {
+            Some(size) if size > self.max_read_bytes => Err(too_large(self.max_read_bytes)),
+            _ => Ok(()),
+        }
+    }
+
+    fn open(&self) -> io::Result<Clipboard> {
+        self.open_attempts(self.attempts)
+    }
+
+    fn open_for_write(&self) -> Result<Clipboard, Box<dyn Error + Send + Sync>> {
+        let attempts = match self.write_policy {
+            WritePolicy::Retry => self.attempts,
+            WritePolicy::FailFast => 1,
+        };
+        self.open_attempts(attempts)
+            .map_err(|_| ClipboardBusy { policy: self.write_policy, attempts: attempts.max(1) }.into())
+    }
+
+    fn open_attempts(&self, attempts: u32) -> io::Result<Clipboard> {
+        let mut attempt = 1;
+        loop {
+            match Clipboard::new() {
+                Err(_) if attempt < attempts => {
+                    attempt += 1;
+                    thread::sleep(self.attempt_delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Run `f` with the clipboard held open, retrying the open like every
+    /// other operation, so several custom format operations can be batched
+    /// without another application getting in between.
+    ///
+    /// The clipboard is closed again once `f` returns.
+    pub fn with_open<R>(&mut self, f: impl FnOnce(&OpenClipboard) -> R) -> Result<R, Box<dyn Error + Send + Sync>> {
+        let clipboard = self.open()?;
+        Ok(f(&OpenClipboard { clipboard: &clipboard }))
+    }
+
+    /// Open the clipboard once for a series of operations, e.g. in a tight
+    /// sync loop where opening and closing it for every call dominates.
+    ///
+    /// Other applications can't access the clipboard until the session is
+    /// dropped, so keep it short.
+    pub fn session(&mut self) -> Result<WindowsClipboardSession, Box<dyn Error + Send + Sync>> {
+        Ok(WindowsClipboardSession { context: *self, clipboard: self.open()? })
+    }
+
+    // sessions hold the clipboard open, so retrying a failed read in between
+    // would only keep the owner from finishing
+    fn read(&self, clipboard: &Clipboard, target: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let format = format_id(target)?;
+        self.check_size(clipboard, format)?;
+        decode_text_alias(target, read_present(clipboard, format)?)
+    }
+
+    /// List the format ids that were genuinely placed on the clipboard.
+    ///
+    /// Windows synthesizes `CF_LOCALE` (16) for any text, and `CF_TEXT` (1) and
+    /// `CF_OEMTEXT` (7) whenever `CF_UNICODETEXT` (13) is present; those ids are
+    /// filtered out, so setting a string reports only `13`.
+    pub fn list_user_targets(&self) -> Result<Vec<u32>, Box<dyn Error + Send + Sync>> {
+        Ok(user_formats(&self.open()?))
+    }
+
+    /// Read every format placed on the clipboard along with its data, keyed
+    /// by format id as accepted by `get_target_contents`.
+    ///
+    /// All formats are read while holding the clipboard open once, so the
+    /// result can't mix data from different writes. Synthesized formats and
+    /// formats that can't be read as plain bytes (e.g. `CF_BITMAP` handles)
+    /// are skipped.
+    pub fn get_all_contents(&mut self) -> Result<Vec<TargetData>, Box<dyn Error + Send + Sync>> {
+        Ok(snapshot(&self.open()?).into_iter()
+            .map(|(format, data)| (format.to_string(), data))
+            .collect())
+    }
+
+    /// Place a list of files on the clipboard as `CF_HDROP`, the way Explorer
+    /// does when copying files.
+    ///
+    /// Every path must be absolute and exist, otherwise an error is returned
+    /// before the clipboard is touched; relative paths would silently break
+    /// pasting in Explorer. Use `set_files_unchecked` to set arbitrary paths.
+    pub fn set_files<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        validate_files(paths)?;
+        self.set_files_unchecked(paths)
+    }
+
+    /// Place a list of files on the clipboard as `CF_HDROP` without validating them
+    pub fn set_files_unchecked<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let clipboard = self.open_for_write()?;
+        clipboard.empty()?;
+        Ok(set_data(CF_HDROP, &drop_files(paths))?)
+    }
+
+    /// Place a list of files on the clipboard like `set_files`, additionally
+    /// writing `Preferred DropEffect` so that the pasting application knows
+    /// whether to move (`cut`) or copy them.
+    pub fn set_files_with_effect<P: AsRef<Path>>(&mut self, paths: &[P], cut: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        validate_files(paths)?;
+        let effect = if cut { DROPEFFECT_MOVE } else { DROPEFFECT_COPY };
+        let format = register_format(formats::DROP_EFFECT)?;
+        let clipboard = self.open_for_write()?;
+        clipboard.empty()?;
+        set_data(CF_HDROP, &drop_files(paths))?;
+        Ok(set_data(format, &effect.to_le_bytes())?)
+    }
+
+    /// Read the list of files on the clipboard from `CF_HDROP`, or an empty
+    /// list when there are none.
+    ///
+    /// Paths are returned exactly as the owner placed them, so long paths
+    /// beyond `MAX_PATH`, with or without the `\\?\` prefix, and non-ASCII
+    /// names survive intact.
+    pub fn get_files(&mut self) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+        let clipboard = self.open()?;
+        self.check_size(&clipboard, CF_HDROP)?;
+        match read_format(&clipboard, CF_HDROP) {
+            Ok(ref data) if data.is_empty() => Ok(Vec::new()),
+            Err(ref e) if e.raw_os_error() == Some(ERROR_NOT_FOUND) => Ok(Vec::new()),
+            result => parse_drop_files(&result?),
+        }
+    }
+
+    /// Read the list of files on the clipboard like `get_files`, either as
+    /// paths or as `file://` urls, e.g. `file:///C:/My%20Files/a.txt` or
+    /// `file://server/share/a.txt` for UNC paths.
+    pub fn get_files_as(&mut self, format: FileFormat) -> Result<FileList, Box<dyn Error + Send + Sync>> {
+        let paths = self.get_files()?;
+        Ok(match format {
+            FileFormat::Paths => FileList::Paths(paths),
+            FileFormat::Urls => FileList::Urls(paths.iter().map(|path| file_url(path)).collect()),
+        })
+    }
+
+    /// Read back `Preferred DropEffect`: `Some(true)` when the files on the
+    /// clipboard were cut, `Some(false)` when copied and `None` when the
+    /// format is not present.
+    pub fn drop_effect(&self) -> Result<Option<bool>, Box<dyn Error + Send + Sync>> {
+        let format = register_format(formats::DROP_EFFECT)?;
+        let clipboard = self.open()?;
+        if !Clipboard::is_format_avail(format) {
+            return Ok(None);
+        }
+        let mut effect = [0u8; 4];
+        clipboard.get(format, &mut effect)?;
+        Ok(Some(u32::from_le_bytes(effect) & DROPEFFECT_MOVE != 0))
+    }
+}
+
+/// The clipboard held open by `WindowsClipboardContext::with_open`
+///
+/// Unlike `Clipboard::set`, `set` doesn't empty the clipboard, so empty it
+/// once and then set every format of the batch.
+pub struct OpenClipboard<'a> {
+    clipboard: &'a Clipboard,
+}
+
+impl<'a> OpenClipboard<'a> {
+    /// Empty the clipboard, taking ownership of it for the following `set`s
+    pub fn empty(&self) -> io::Result<()> {
+        self.clipboard.empty()
+    }
+
+    /// Add `data` under `format` next to the formats set so far
+    pub fn set(&self, format: u32, data: &[u8]) -> io::Result<()> {
+        set_data(format, data)
+    }
+
+    /// Read `format` into `data`, returning how many bytes were written
+    pub fn get(&self, format: u32, data: &mut [u8]) -> io::Result<usize> {
+        self.clipboard.get(format, data)
+    }
+
+    /// Size of the data under `format`, `None` when it is absent
+    pub fn size(&self, format: u32) -> Option<usize> {
+        self.clipboard.size(format)
+    }
+}
+
+/// Clipboard held open by `WindowsClipboardContext::session` until dropped
+pub struct WindowsClipboardSession {
+    context: WindowsClipboardContext,
+    clipboard: Clipboard,
+}
+
+impl WindowsClipboardSession {
+    /// Like `get_target_contents`, without opening the clipboard again or
+    /// retrying a failed read
+    pub fn get(&self, target: impl ToString) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.context.read(&self.clipboard, &target.to_string())
+    }
+
+    /// Like `set_target_contents`, keeping the other formats without opening
+    /// the clipboard again
+    pub fn set(&self, target: impl ToString, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let target = target.to_string();
+        let format = format_id(&target)?;
+        check_bitmap_encoding(format, data)?;
+        let data = encode_text_alias(&target, data)?;
+        Ok(replace_format(&self.clipboard, format, &data)?)
+    }
+
+    /// Like `list_user_targets`, without opening the clipboard again
+    pub fn list(&self) -> Vec<u32> {
+        user_formats(&self.clipboard)
+    }
+
+    /// Like `clear`, without opening the clipboard again
+    pub fn clear(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(self.clipboard.empty()?)
+    }
+}
+
+fn validate_files<P: AsRef<Path>>(paths: &[P]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for path in paths {
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(format!("{} is not an absolute path", path.display()).into());
+        }
+        if !path.exists() {
+            return Err(format!("{} does not exist", path.display()).into());
+        }
+    }
+    Ok(())
+}
+
+// DROPFILES header followed by wide, NUL-separated and double-NUL-terminated paths
+fn drop_files<P: AsRef<Path>>(paths: &[P]) -> Vec<u8> {
+    let mut data = Vec::new();
+    // pFiles, pt.x, pt.y, fNC, fWide
+    for field in &[DROPFILES_SIZE, 0, 0, 0, 1] {
+        data.extend_from_slice(&field.to_le_bytes());
+    }
+    for path in paths {
+        for unit in path.as_ref().as_os_str().encode_wide().chain(Some(0)) {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+    }
+    data.extend_from_slice(&[0, 0]);
+    data
+}
+
+// inverse of drop_files, also accepting the ANSI layout older applications use
+fn parse_drop_files(data: &[u8]) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    if data.len() < DROPFILES_SIZE as usize {
+        return Err("CF_HDROP data is shorter than its DROPFILES header".into());
+    }
+    let field = |offset: usize| u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+    let list = data.get(field(0) as usize..).ok_or("CF_HDROP file list starts past its end")?;
+    let wide = field(16) != 0;
+    let names: Vec<OsString> = if wide {
+        let units: Vec<u16> = list.chunks(2)
+            .filter(|chunk| chunk.len() == 2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+            .collect();
+        units.split(|&unit| unit == 0)
+            .take_while(|name| !name.is_empty())
+            .map(OsString::from_wide)
+            .collect()
+    } else {
+        list.split(|&byte| byte == 0)
+            .take_while(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned().into())
+            .collect()
+    };
+    Ok(names.into_iter().map(PathBuf::from).collect())
+}
+
+// Clipboard::set empties the clipboard before every format, which would
+// leave only the last format of a multi-target write. The clipboard must be
+// open and owned, i.e. emptied once, by the caller.
+fn set_data(format: u32, data: &[u8]) -> io::Result<()> {
+    if data.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Windows can't hold zero-sized clipboard data"));
+    }
+    unsafe {
+        let handle = GlobalAlloc(GMEM_MOVEABLE, data.len() as SIZE_T);
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let lock = GlobalLock(handle) as *mut u8;
+        if lock.is_null() {
+            let e = io::Error::last_os_error();
+            GlobalFree(handle);
+            return Err(e);
+        }
+        ptr::copy_nonoverlapping(data.as_ptr(), lock, data.len());
+        GlobalUnlock(handle);
+        // the system owns the memory once SetClipboardData succeeds
+        if SetClipboardData(format, handle).is_null() {
+            let e = io::Error::last_os_error();
+            GlobalFree(handle);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn user_formats(clipboard: &Clipboard) -> Vec<u32> {
+    let formats: Vec<u32> = clipboard.enum_formats().collect();
+    let has_unicode = formats.contains(&CF_UNICODETEXT);
+    formats.into_iter()
+        .filter(|&format| !is_synthesized(format, has_unicode))
+        .collect()
+}
+
+fn read_format(clipboard: &Clipboard, format: u32) -> io::Result<Vec<u8>> {
+    match clipboard.size(format) {
+        Some(size) => {
+            let mut data = vec![0; size];
+            let len = clipboard.get(format, &mut data)?;
+            data.truncate(len);
+            Ok(data)
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+// empty when the format is genuinely absent
+fn read_present(clipboard: &Clipboard, format: u32) -> io::Result<Vec<u8>> {
+    match read_format(clipboard, format) {
+        Err(ref e) if e.raw_os_error() == Some(ERROR_NOT_FOUND) => Ok(Vec::new()),
+        result => result,
+    }
+}
+
+// copy of every format that can be read back as plain bytes
+fn snapshot(clipboard: &Clipboard) -> Vec<(u32, Vec<u8>)> {
+    user_formats(clipboard).into_iter()
+        .filter_map(|format| {
+            let mut data = vec![0; clipboard.size(format)?];
+            clipboard.get(format, &mut data).ok()?;
+            Some((format, data))
+        })
+        .collect()
+}
+
+// Only the owner can add a format and owning the clipboard takes emptying it,
+// so the formats to keep are read first and written back. Text replaces every
+// other text format, which would otherwise disagree with it.
+fn replace_format(clipboard: &Clipboard, format: u32, data: &[u8]) -> io::Result<()> {
+    let kept: Vec<(u32, Vec<u8>)> = snapshot(clipboard).into_iter()
+        .filter(|&(other, _)| other != format && !(is_text(format) && is_text(other)))
+        .collect();
+    clipboard.empty()?;
+    for (other, other_data) in kept {
+        set_data(other, &other_data)?;
+    }
+    set_data(format, data)
+}
+
+fn is_text(format: u32) -> bool {
+    format == CF_UNICODETEXT || format == CF_TEXT || format == CF_OEMTEXT
+}
+
+// targets are either numeric format ids or names of registered formats,
+// the UTF-8 text aliases stand for CF_UNICODETEXT
+fn format_id(target: &str) -> io::Result<u32> {
+    if formats::TEXT_ALIASES.contains(&target) {
+        return Ok(CF_UNICODETEXT);
+    }
+    match target.parse() {
+        Ok(format) => Ok(format),
+        Err(_) => register_format(target),
+    }
+}
+
+// UTF-8 text set under an alias, converted to the NUL-terminated UTF-16 of
+// CF_UNICODETEXT; empty data is left to be refused by set_data
+fn encode_text_alias<'a>(target: &str, data: &'a [u8]) -> Result<Cow<'a, [u8]>, Box<dyn Error + Send + Sync>> {
+    if !formats::TEXT_ALIASES.contains(&target) || data.is_empty() {
+        return Ok(Cow::Borrowed(data));
+    }
+    let text = str::from_utf8(data)
+        .map_err(|_| format!("invalid text encoding for target {}: expected UTF-8", target))?;
+    Ok(Cow::Owned(text.encode_utf16().chain(Some(0)).flat_map(|unit| unit.to_le_bytes().to_vec()).collect()))
+}
+
+// CF_UNICODETEXT read under an alias, converted back to UTF-8
+fn decode_text_alias(target: &str, data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    if !formats::TEXT_ALIASES.contains(&target) {
+        return Ok(data);
+    }
+    let units: Vec<u16> = data.chunks(2)
+        .filter(|chunk| chunk.len() == 2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    Ok(String::from_utf16(&units)?.into_bytes())
+}
+
+// bitmap formats hold a raw BITMAPINFO; an encoded image would be stored as garbage
+fn check_bitmap_encoding(format: u32, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if format != CF_DIB && format != CF_DIBV5 {
+        return Ok(());
+    }
+    let encoding = if data.starts_with(b"\x89PNG") {
+        "PNG"
+    } else if data.starts_with(b"\xFF\xD8\xFF") {
+        "JPEG"
+    } else if data.starts_with(b"GIF8") {
+        "GIF"
+    } else {
+        return Ok(());
+    };
+    Err(format!("unsupported image encoding {}, expected DIB; \
+                 use set_image from the `image` feature to convert it", encoding).into())
+}
+
+fn is_synthesized(format: u32, has_unicode: bool) -> bool {
+    match format {
+        CF_LOCALE => true,
+        CF_TEXT | CF_OEMTEXT => has_unicode,
+        _ => false,
+    }
+}
+
+// the \\?\ prefix only lifts MAX_PATH and has no url form
+fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let path = match path.strip_prefix("//?/UNC/") {
+        Some(unc) => format!("//{}", unc),
+        None => path.strip_prefix("//?/").unwrap_or(&path).to_owned(),
+    };
+    // UNC paths name the host, drive paths have none
+    let mut url = String::from(if path.starts_with("//") { "file:" } else { "file:///" });
+    percent_encode(path.as_bytes(), &mut url);
+    url
+}
 
 impl ClipboardProvider for WindowsClipboardContext {
-    fn new() -> Result<Self, Box<dyn Error>> {
-        Ok(WindowsClipboardContext)
+    fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(WindowsClipboardContext {
+            attempts: DEFAULT_ATTEMPTS,
+            attempt_delay: DEFAULT_ATTEMPT_DELAY,
+            max_read_bytes: DEFAULT_MAX_READ_BYTES,
+            write_policy: WritePolicy::Retry,
+            image_preference: formats::IMAGES,
+        })
+    }
+    fn get_contents(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let clipboard = self.open()?;
+        self.check_size(&clipboard, CF_UNICODETEXT)?;
+        Ok(strip_bom(clipboard.get_string()?))
+    }
+    fn set_contents(&mut self, data: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(self.open_for_write()?.set_string(&data)?)
+    }
+    fn backend_name(&self) -> &'static str {
+        "windows"
+    }
+    // SetClipboardData is only accepted from the owner, so the other formats
+    // are written back after emptying the clipboard
+    fn add_contents(&mut self, data: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let text: Vec<u8> = data.encode_utf16().chain(Some(0)).flat_map(|unit| unit.to_le_bytes().to_vec()).collect();
+        let clipboard = self.open_for_write()?;
+        Ok(replace_format(&clipboard, CF_UNICODETEXT, &text)?)
+    }
+    fn image_preference(&self) -> &'static [&'static str] {
+        self.image_preference
+    }
+    // CF_TEXT is in the ANSI code page; Windows converts it to
+    // CF_UNICODETEXT on request, so Unicode readers see it too
+    fn set_text_raw(&mut self, mut data: Vec<u8>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        data.push(0);
+        let clipboard = self.open_for_write()?;
+        clipboard.empty()?;
+        Ok(set_data(CF_TEXT, &data)?)
+    }
+    fn list_data_targets(&mut self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        Ok(self.list_user_targets()?.into_iter().map(|format| format.to_string()).collect())
+    }
+    fn snapshot_atomic(&mut self) -> Result<Vec<TargetData>, Box<dyn Error + Send + Sync>> {
+        self.get_all_contents()
+    }
+    fn get_target_contents(&mut self, target: impl ToString) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let target = target.to_string();
+        let format = format_id(&target)?;
+        let mut attempt = 1;
+        loop {
+            let clipboard = self.open()?;
+            self.check_size(&clipboard, format)?;
+            match read_present(&clipboard, format) {
+                // the owner may fail to render while it is busy writing, the
+                // clipboard is closed meanwhile so it can finish
+                Err(_) if attempt < self.attempts => {
+                    drop(clipboard);
+                    attempt += 1;
+                    thread::sleep(self.attempt_delay);
+                }
+                result => return decode_text_alias(&target, result?),
+            }
+        }
+    }
+    fn get_target_contents_opt(&mut self, target: impl ToString) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+        let target = target.to_string();
+        if !Clipboard::is_format_avail(format_id(&target)?) {
+            return Ok(None);
+        }
+        Ok(Some(self.get_target_contents(target)?))
+    }
+    fn set_target_contents(&mut self, target: impl ToString, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let session = WindowsClipboardSession { context: *self, clipboard: self.open_for_write()? };
+        session.set(target, data)
+    }
+    // IsClipboardFormatAvailable doesn't require opening the clipboard
+    fn contains_target(&mut self, target: impl ToString) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(Clipboard::is_format_avail(format_id(&target.to_string())?))
+    }
+    // delayed rendering only happens in GetClipboardData
+    fn peek_target(&mut self, target: impl ToString) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        self.contains_target(target)
     }
-    fn get_contents(&mut self) -> Result<String, Box<dyn Error>> {
-        Ok(get_clipboard_string()?)
+    fn set_multiple_targets(&mut self, targets: TargetMap<impl ToString>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if targets.is_empty() {
+            return self.clear();
+        }
+        // resolve every format before the clipboard is touched
+        let targets = targets.into_iter()
+            .map(|(key, value)| {
+                let key = key.to_string();
+                let format = format_id(&key)?;
+                check_bitmap_encoding(format, value)?;
+                Ok((format, encode_text_alias(&key, value)?))
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+        let clipboard = self.open_for_write()?;
+        let previous = snapshot(&clipboard);
+        clipboard.empty()?;
+        for &(format, ref value) in &targets {
+            if let Err(e) = set_data(format, value) {
+                // restore the previous contents so a failed write leaves no partial state
+                let _ = clipboard.empty();
+                for &(format, ref data) in &previous {
+                    let _ = set_data(format, data);
+                }
+                return Err(e.into());
+            }
+        }
+        Ok(())
     }
-    fn set_contents(&mut self, data: String) -> Result<(), Box<dyn Error>> {
-        Ok(set_clipboard_string(&data)?)
+    fn clear(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self.open_for_write() {
+            Ok(clipboard) => {
+                clipboard.empty()?;
+                Ok(())
+            }
+            // another application holding the clipboard open only matters
+            // if there is something left to clear
+            Err(_) if Clipboard::count_formats()? == 0 => Ok(()),
+            Err(e) => Err(e),
+        }
     }
 }
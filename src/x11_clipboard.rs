@@ -14,14 +14,27 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use std::env;
 use std::error::Error;
-use std::time::Duration;
+use std::fmt::Display;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use std::marker::PhantomData;
 use common::*;
-use x11_clipboard_crate::Atoms;
+use formats;
+use x11_clipboard_crate::{Atoms, Context};
 use x11_clipboard_crate::Clipboard as X11Clipboard;
+use x11_clipboard_crate::xcb;
 use x11_clipboard_crate::xcb::xproto::Atom;
 use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// How long a single read waits for the selection owner to respond
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub trait Selection {
     fn atom(atoms: &Atoms) -> Atom;
@@ -43,60 +56,821 @@ impl Selection for Clipboard {
     }
 }
 
-pub struct X11ClipboardContext<S = Clipboard>(X11Clipboard, PhantomData<S>)
+/// Context owning its own X11 connections. It is deliberately not `Clone`:
+/// a copy would share the selection ownership and the transfer property.
+///
+/// X11 has no clipboard storage, the owner serves the data on request. Once
+/// the context is dropped its connections close and whatever it set is
+/// gone, unless a clipboard manager took a copy; see `into_detached` to
+/// outlive the context.
+pub struct X11ClipboardContext<S = Clipboard>
+where
+    S: Selection,
+{
+    // shared between the two selections of an X11DualContext
+    clipboard: Arc<X11Clipboard>,
+    property: Atom,
+    // kept to intern the property again after reconnecting, None for the default
+    property_name: Option<String>,
+    read_timeout: Duration,
+    max_read_bytes: usize,
+    image_preference: &'static [&'static str],
+    // half of an X11DualContext, whose halves only reconnect together
+    dual: bool,
+    selection: PhantomData<S>,
+}
+
+impl<S> X11ClipboardContext<S>
 where
-    S: Selection;
+    S: Selection,
+{
+    /// Create a context that receives transfers through the given property
+    /// instead of the default `THIS_CLIPBOARD_OUT`.
+    ///
+    /// This only matters when several contexts load from the same window at
+    /// once (e.g. multiple clients of this crate embedded in one process), as
+    /// concurrent transfers through a shared property can clobber each other.
+    pub fn with_property(name: &str) -> Result<X11ClipboardContext<S>, Box<dyn Error + Send + Sync>> {
+        let clipboard = connect()?;
+        let property = clipboard.getter.get_atom(name).map_err(x11_error)?;
+        Ok(X11ClipboardContext {
+            clipboard: Arc::new(clipboard),
+            property,
+            property_name: Some(name.to_owned()),
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            max_read_bytes: DEFAULT_MAX_READ_BYTES,
+            image_preference: formats::IMAGES,
+            dual: false,
+            selection: PhantomData,
+        })
+    }
+
+    fn from_shared(clipboard: Arc<X11Clipboard>) -> X11ClipboardContext<S> {
+        let property = clipboard.getter.atoms.property;
+        X11ClipboardContext {
+            clipboard,
+            property,
+            property_name: None,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            max_read_bytes: DEFAULT_MAX_READ_BYTES,
+            image_preference: formats::IMAGES,
+            dual: false,
+            selection: PhantomData,
+        }
+    }
+
+    /// Bound how long a single read waits for the selection owner before
+    /// giving up with a timeout error, `DEFAULT_READ_TIMEOUT` by default.
+    ///
+    /// Reads are not retried, so this is the whole time a `get_*` call may
+    /// block; there is no separate polling interval on X11.
+    pub fn set_read_timeout(&mut self, read_timeout: Duration) {
+        self.read_timeout = read_timeout;
+    }
+
+    /// Abort reads of targets larger than `max_read_bytes` with an error,
+    /// `DEFAULT_MAX_READ_BYTES` by default.
+    ///
+    /// Incremental transfers are abandoned as soon as the announced or
+    /// accumulated size exceeds the limit.
+    pub fn set_max_read_bytes(&mut self, max_read_bytes: usize) {
+        self.max_read_bytes = max_read_bytes;
+    }
+
+    /// Set the image targets `get_image_bytes` tries, most preferred first,
+    /// e.g. `&[formats::PNG, formats::JPEG]` to skip uncompressed bitmaps.
+    /// `get_image` keeps the platform's order.
+    pub fn set_image_preference(&mut self, targets: &'static [&'static str]) {
+        self.image_preference = targets;
+    }
+
+    /// Set the selection text from raw bytes, without the `String` round trip
+    /// of `set_contents`.
+    ///
+    /// The bytes are offered as `UTF8_STRING` as is and are not validated,
+    /// unlike `set_target_contents`; most readers assume UTF-8 and will show
+    /// anything else garbled. Latin-1 text belongs under the `STRING` target,
+    /// which `set_text_raw` sets instead.
+    pub fn set_contents_bytes(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let target = self.clipboard.setter.atoms.utf8_string;
+        self.set_raw(target, data)
+    }
+
+    // replaces the selection with unvalidated bytes under a single target
+    fn set_raw(&mut self, target: Atom, data: Vec<u8>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut targets = HashMap::new();
+        targets.insert(target, data);
+        self.clipboard.store_multiple(
+            S::atom(&self.clipboard.setter.atoms),
+            targets,
+        ).map_err(x11_error)
+    }
+
+    /// Keep serving what this context set from a background thread after the
+    /// context itself is gone, e.g. for a command line tool that exits its
+    /// main logic right after copying.
+    ///
+    /// The selection is served until `DetachedSelection::stop` is called or
+    /// the process exits; dropping the handle doesn't stop it.
+    pub fn into_detached(self) -> DetachedSelection {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = stopped.clone();
+        let clipboard = self.clipboard;
+        let thread = thread::spawn(move || {
+            let (lock, condvar) = &*signal;
+            let mut stopped = lock.lock().unwrap();
+            while !*stopped {
+                stopped = condvar.wait(stopped).unwrap();
+            }
+            drop(clipboard);
+        });
+        DetachedSelection { stopped, thread }
+    }
+
+    /// Set the text and serve it from a background thread until `ttl` has
+    /// passed, then clear the selection unless another client took it over
+    /// in the meantime, e.g. for a password manager that moves on right
+    /// after copying. Unlike `set_contents_expiring`, the text stays
+    /// pasteable for the whole `ttl` once the context is gone.
+    ///
+    /// Cancelling the handle stops serving too, as nothing else holds the
+    /// selection.
+    pub fn set_contents_detached_expiring(mut self, data: String, ttl: Duration) -> Result<ClearHandle, Box<dyn Error + Send + Sync>>
+    where
+        S: 'static,
+    {
+        self.set_contents(data)?;
+        let clipboard = self.clipboard;
+        let selection = S::atom(&clipboard.setter.atoms);
+        Ok(ClearHandle::spawn(ttl, move || disown(&clipboard.setter, &[selection])))
+    }
+
+    /// Access the underlying `x11-clipboard` connection pair.
+    ///
+    /// Every context owns its own getter and setter connections, so two
+    /// contexts in one process act as independent selection owners, which is
+    /// handy for exchanging data in tests without an external tool.
+    pub fn x11_clipboard(&self) -> &X11Clipboard {
+        &self.clipboard
+    }
+
+    /// Place a list of files on the selection as `text/uri-list`, together with
+    /// the markers GNOME (`x-special/gnome-copied-files`) and KDE
+    /// (`application/x-kde-cutselection`) file managers use to tell whether
+    /// the files were cut or copied.
+    ///
+    /// Every path must be absolute, as a `file://` url can't hold a relative
+    /// one.
+    pub fn set_files_with_effect<P: AsRef<Path>>(&mut self, paths: &[P], cut: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(path) = paths.iter().map(AsRef::as_ref).find(|path| !path.is_absolute()) {
+            return Err(format!("{} is not an absolute path", path.display()).into());
+        }
+        let uris: Vec<String> = paths.iter().map(|path| file_uri(path.as_ref())).collect();
+        let uri_list = uris.join("\r\n");
+        let gnome_files = format!("{}\n{}", if cut { "cut" } else { "copy" }, uris.join("\n"));
+        let kde_cut = if cut { "1" } else { "0" };
+        let mut targets = HashMap::new();
+        targets.insert(formats::URI_LIST, uri_list.as_bytes());
+        targets.insert(formats::GNOME_FILES, gnome_files.as_bytes());
+        targets.insert(formats::KDE_CUT, kde_cut.as_bytes());
+        self.set_multiple_targets(targets)
+    }
+
+    /// Read the files on the selection from `text/uri-list`, either as
+    /// paths or as the urls they were offered as, or an empty list when
+    /// there are none.
+    ///
+    /// Urls of other hosts or schemes are only returned as `FileFormat::Urls`.
+    pub fn get_files_as(&mut self, format: FileFormat) -> Result<FileList, Box<dyn Error + Send + Sync>> {
+        let uri_list = String::from_utf8(self.get_target_contents_opt(formats::URI_LIST)?.unwrap_or_default())?;
+        // lines starting with # are comments
+        let uris = uri_list.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        Ok(match format {
+            FileFormat::Paths => FileList::Paths(uris.filter_map(file_path).collect()),
+            FileFormat::Urls => FileList::Urls(uris.map(str::to_owned).collect()),
+        })
+    }
+
+    /// Read back the cut/copy marker: `Some(true)` when the files on the
+    /// selection were cut, `Some(false)` when copied and `None` when neither
+    /// the GNOME nor the KDE marker is present.
+    pub fn drop_effect(&mut self) -> Result<Option<bool>, Box<dyn Error + Send + Sync>> {
+        let gnome_files = self.get_target_contents(formats::GNOME_FILES)?;
+        match gnome_files.split(|&byte| byte == b'\n').next() {
+            Some(b"cut") => return Ok(Some(true)),
+            Some(b"copy") => return Ok(Some(false)),
+            _ => (),
+        }
+        match &self.get_target_contents(formats::KDE_CUT)?[..] {
+            b"1" => Ok(Some(true)),
+            b"0" => Ok(Some(false)),
+            _ => Ok(None),
+        }
+    }
+
+    fn load(&self, target: Atom, progress: impl FnMut(usize)) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        load_with_progress(
+            &self.clipboard.getter,
+            S::atom(&self.clipboard.getter.atoms),
+            target,
+            self.property,
+            self.read_timeout,
+            self.max_read_bytes,
+            progress,
+        )
+    }
+
+    // X11 times are milliseconds of server uptime, so the server's current
+    // time is needed to place them on the wall clock. Appending nothing to a
+    // property of our own window makes the server report its time.
+    fn server_time(&self) -> Result<xcb::Timestamp, Box<dyn Error + Send + Sync>> {
+        let getter = &self.clipboard.getter;
+        let connection = &getter.connection;
+        let property = intern(getter, "_CLIPBOARD_SERVER_TIME")?;
+        let empty: &[u8] = &[];
+        xcb::change_property(connection, xcb::PROP_MODE_APPEND as u8, getter.window, property, xcb::ATOM_INTEGER, 8, empty);
+        connection.flush();
+        let deadline = Instant::now() + self.read_timeout;
+        while let Some(event) = wait_for_event(connection, deadline)? {
+            if event.response_type() & !0x80 != xcb::PROPERTY_NOTIFY {
+                continue;
+            }
+            let event = unsafe { xcb::cast_event::<xcb::PropertyNotifyEvent>(&event) };
+            if event.atom() == property {
+                return Ok(event.time());
+            }
+        }
+        Err("timed out waiting for the server time".into())
+    }
+
+    /// Read every target the selection owner offers along with its data,
+    /// skipping meta targets like `TARGETS` and targets that fail to convert.
+    ///
+    /// Every target is converted separately, so an owner taking over the
+    /// selection meanwhile can leave the result mixing two writes.
+    pub fn get_all_contents(&mut self) -> Result<Vec<TargetData>, Box<dyn Error + Send + Sync>> {
+        let mut contents = Vec::new();
+        for target in self.list_data_targets()? {
+            if let Ok(data) = self.get_target_contents(target.as_str()) {
+                contents.push((target, data));
+            }
+        }
+        Ok(contents)
+    }
+
+    // the transfer property as interned on the given connection
+    fn property_on(&self, clipboard: &X11Clipboard) -> Result<Atom, Box<dyn Error + Send + Sync>> {
+        Ok(match self.property_name {
+            Some(ref name) => clipboard.getter.get_atom(name).map_err(x11_error)?,
+            None => clipboard.getter.atoms.property,
+        })
+    }
+
+    // empty when the selection has no owner or the owner doesn't answer TARGETS
+    fn offered_targets(&self) -> Result<Vec<Atom>, Box<dyn Error + Send + Sync>> {
+        Ok(parse_atoms(&self.load(self.clipboard.getter.atoms.targets, |_| ())?))
+    }
+}
+
+impl X11ClipboardContext<Clipboard> {
+    /// Ask the running clipboard manager to take a copy of what this context
+    /// set on CLIPBOARD, so it survives the context being dropped, following
+    /// the `SAVE_TARGETS` convention.
+    ///
+    /// Returns whether a manager accepted; `false` when none is running or
+    /// it refused. Waits up to the read timeout while the manager copies,
+    /// and fails when this context doesn't own CLIPBOARD.
+    pub fn request_persistence(&mut self) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let setter = &self.clipboard.setter;
+        let owner = xcb::get_selection_owner(&setter.connection, setter.atoms.clipboard).get_reply().map_err(x11_error)?;
+        if owner.owner() != setter.window {
+            return Err("CLIPBOARD isn't owned by this context, there is nothing to persist".into());
+        }
+        let getter = &self.clipboard.getter;
+        let connection = &getter.connection;
+        let manager = intern(getter, "CLIPBOARD_MANAGER")?;
+        let owner = xcb::get_selection_owner(connection, manager).get_reply().map_err(x11_error)?.owner();
+        if owner == xcb::NONE {
+            return Ok(false);
+        }
+        let save_targets = intern(getter, "SAVE_TARGETS")?;
+        xcb::convert_selection(connection, getter.window, manager, save_targets, self.property, xcb::CURRENT_TIME);
+        connection.flush();
+        let deadline = Instant::now() + self.read_timeout;
+        while let Some(event) = wait_for_event(connection, deadline)? {
+            if event.response_type() & !0x80 != xcb::SELECTION_NOTIFY {
+                continue;
+            }
+            let event = unsafe { xcb::cast_event::<xcb::SelectionNotifyEvent>(&event) };
+            if event.selection() == manager {
+                xcb::delete_property(connection, getter.window, self.property);
+                connection.flush();
+                return Ok(event.property() != xcb::NONE);
+            }
+        }
+        Err("timed out waiting for the clipboard manager".into())
+    }
+
+    /// Like `get_contents`, but reads PRIMARY when CLIPBOARD has no owner or
+    /// holds empty text, e.g. right after selecting text with the mouse.
+    ///
+    /// CLIPBOARD's error is returned when PRIMARY has nothing either.
+    pub fn get_contents_with_primary_fallback(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let contents = self.get_contents();
+        match contents {
+            Ok(ref text) if !text.is_empty() => return contents,
+            _ => (),
+        }
+        let primary = load_with_progress(
+            &self.clipboard.getter,
+            self.clipboard.getter.atoms.primary,
+            self.clipboard.getter.atoms.utf8_string,
+            self.property,
+            self.read_timeout,
+            self.max_read_bytes,
+            |_| (),
+        );
+        match primary.map(String::from_utf8) {
+            Ok(Ok(text)) => if text.is_empty() { contents } else { Ok(strip_bom(text)) },
+            _ => contents,
+        }
+    }
+}
+
+// x11-clipboard and xcb errors hold raw pointers and aren't Send, so only
+// their message is kept
+fn x11_error<E: Display>(e: E) -> Box<dyn Error + Send + Sync> {
+    e.to_string().into()
+}
+
+// TARGETS replies are a list of 32-bit atoms, which xcb hands over in host byte order
+fn parse_atoms(data: &[u8]) -> Vec<Atom> {
+    data.chunks(4)
+        .filter(|chunk| chunk.len() == 4)
+        .map(|chunk| Atom::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+// targets describing the selection rather than holding its data
+const META_TARGETS: &[&str] = &["TARGETS", "TIMESTAMP", "MULTIPLE", "SAVE_TARGETS", "DELETE", "INCR"];
+
+fn meta_targets(context: &Context) -> Result<Vec<Atom>, Box<dyn Error + Send + Sync>> {
+    META_TARGETS.iter()
+        .map(|name| context.get_atom(name).map_err(x11_error))
+        .collect()
+}
+
+// get_atom interns names that don't exist yet, so new targets can be set;
+// storing under NONE would silently offer nothing
+fn intern(context: &Context, name: &str) -> Result<Atom, Box<dyn Error + Send + Sync>> {
+    match context.get_atom(name).map_err(x11_error)? {
+        xcb::NONE => Err(format!("could not intern target {}", name).into()),
+        atom => Ok(atom),
+    }
+}
+
+// the xcb connection error alone doesn't tell a misconfigured environment
+// (e.g. a headless session) from a broken server
+fn connect() -> Result<X11Clipboard, Box<dyn Error + Send + Sync>> {
+    X11Clipboard::new().map_err(|e| {
+        let display = match env::var("DISPLAY") {
+            Ok(ref display) if !display.is_empty() => format!("DISPLAY={}", display),
+            _ => "DISPLAY is not set".to_owned(),
+        };
+        format!("no X11 display server could be reached ({}): {}", display, e).into()
+    })
+}
+
+// Same transfer as X11Clipboard::load, which doesn't report INCR chunks as
+// they arrive. The getter window only ever receives events for our own
+// transfers, so there's no need to filter by requestor.
+fn load_with_progress(
+    getter: &Context,
+    selection: Atom,
+    target: Atom,
+    property: Atom,
+    timeout: Duration,
+    max_bytes: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let connection = &getter.connection;
+    xcb::convert_selection(connection, getter.window, selection, target, property, xcb::CURRENT_TIME);
+    connection.flush();
+    let mut data = Vec::new();
+    let mut incr = false;
+    // the timeout applies to each step, so a slow but steady INCR transfer
+    // can take as long as it needs
+    let mut deadline = Instant::now() + timeout;
+    loop {
+        let event = match wait_for_event(connection, deadline)? {
+            Some(event) => event,
+            None => return Err("timed out waiting for the selection owner".into()),
+        };
+        match event.response_type() & !0x80 {
+            xcb::SELECTION_NOTIFY => {
+                let event = unsafe { xcb::cast_event::<xcb::SelectionNotifyEvent>(&event) };
+                if event.selection() != selection {
+                    continue;
+                }
+                if event.property() == xcb::NONE {
+                    break;
+                }
+                // lengths are in 32-bit units, one more than the limit tells whether it's exceeded;
+                // any type is accepted, owners often answer with a type other than the
+                // target (e.g. STRING for TEXT) and the data is still what was asked for
+                let reply = xcb::get_property(connection, false, getter.window, property, xcb::ATOM_ANY,
+                                              0, (max_bytes / 4 + 1) as u32)
+                    .get_reply().map_err(x11_error)?;
+                if reply.type_() == getter.atoms.incr {
+                    // deleting the property asks the owner for the first chunk
+                    if let Some(&size) = reply.value::<u32>().first() {
+                        if size as usize > max_bytes {
+                            xcb::delete_property(connection, getter.window, property);
+                            connection.flush();
+                            return Err(too_large(max_bytes));
+                        }
+                        data.reserve(size as usize);
+                    }
+                    xcb::delete_property(connection, getter.window, property);
+                    connection.flush();
+                    incr = true;
+                    deadline = Instant::now() + timeout;
+                    continue;
+                }
+                if reply.bytes_after() > 0 || reply.value_len() as usize > max_bytes {
+                    xcb::delete_property(connection, getter.window, property);
+                    connection.flush();
+                    return Err(too_large(max_bytes));
+                }
+                data.extend_from_slice(reply.value());
+                break;
+            }
+            xcb::PROPERTY_NOTIFY if incr => {
+                let event = unsafe { xcb::cast_event::<xcb::PropertyNotifyEvent>(&event) };
+                if event.atom() != property || event.state() != xcb::PROPERTY_NEW_VALUE as u8 {
+                    continue;
+                }
+                // reading with delete set asks the owner for the next chunk
+                let reply = xcb::get_property(connection, true, getter.window, property, xcb::ATOM_ANY, 0, u32::MAX / 4)
+                    .get_reply().map_err(x11_error)?;
+                connection.flush();
+                // a zero-length chunk ends the transfer
+                if reply.value_len() == 0 {
+                    break;
+                }
+                if data.len() + reply.value_len() as usize > max_bytes {
+                    xcb::delete_property(connection, getter.window, property);
+                    connection.flush();
+                    return Err(too_large(max_bytes));
+                }
+                data.extend_from_slice(reply.value());
+                progress(data.len());
+                deadline = Instant::now() + timeout;
+            }
+            _ => (),
+        }
+    }
+    xcb::delete_property(connection, getter.window, property);
+    connection.flush();
+    Ok(data)
+}
+
+// the next event, or `None` once the deadline passed, sleeping on the
+// connection's socket in between
+fn wait_for_event(connection: &xcb::Connection, deadline: Instant) -> Result<Option<xcb::GenericEvent>, Box<dyn Error + Send + Sync>> {
+    loop {
+        if let Some(event) = connection.poll_for_event() {
+            return Ok(Some(event));
+        }
+        // a broken connection would wake poll right away
+        connection.has_error().map_err(x11_error)?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        // rounded up, so the deadline has passed once poll times out
+        let millis = (deadline - now).as_millis() + 1;
+        let mut fd = libc::pollfd { fd: connection.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        unsafe {
+            libc::poll(&mut fd, 1, millis.min(libc::c_int::MAX as u128) as libc::c_int);
+        }
+    }
+}
+
+// percent-encoded file:// uri as used by text/uri-list
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    percent_encode(path.as_os_str().as_bytes(), &mut uri);
+    uri
+}
+
+// only local files have a path, i.e. without a host or on localhost
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let path = match rest.find('/')? {
+        0 => rest,
+        start if &rest[..start] == "localhost" => &rest[start..],
+        _ => return None,
+    };
+    Some(PathBuf::from(OsString::from_vec(percent_decode(path)?)))
+}
 
 impl<S> ClipboardProvider for X11ClipboardContext<S>
 where
     S: Selection,
 {
-    fn new() -> Result<X11ClipboardContext<S>, Box<dyn Error>> {
-        Ok(X11ClipboardContext(X11Clipboard::new()?, PhantomData))
+    fn new() -> Result<X11ClipboardContext<S>, Box<dyn Error + Send + Sync>> {
+        Ok(X11ClipboardContext::from_shared(Arc::new(connect()?)))
     }
 
-    fn get_contents(&mut self) -> Result<String, Box<dyn Error>> {
-        Ok(String::from_utf8(self.0.load(
-            S::atom(&self.0.getter.atoms),
-            self.0.getter.atoms.utf8_string,
-            self.0.getter.atoms.property,
-            Duration::from_secs(3),
-        )?)?)
+    fn get_contents(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(strip_bom(String::from_utf8(load_with_progress(
+            &self.clipboard.getter,
+            S::atom(&self.clipboard.getter.atoms),
+            self.clipboard.getter.atoms.utf8_string,
+            self.property,
+            self.read_timeout,
+            self.max_read_bytes,
+            |_| (),
+        )?)?))
     }
 
-    fn set_contents(&mut self, data: String) -> Result<(), Box<dyn Error>> {
-        Ok(self.0.store(
-            S::atom(&self.0.setter.atoms),
-            self.0.setter.atoms.utf8_string,
-            data,
-        )?)
+    // store only replaces its own target, store_multiple the whole selection
+    fn set_contents(&mut self, data: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.set_contents_bytes(data.into_bytes())
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "x11"
+    }
+
+    // A fresh context wouldn't own the selection, so the timer gives it up
+    // through this context's connections. The value is read over a
+    // connection of its own, as the getter isn't meant for concurrent reads.
+    fn set_contents_expiring(&mut self, data: String, ttl: Duration) -> Result<ClearHandle, Box<dyn Error + Send + Sync>>
+    where
+        Self: 'static,
+    {
+        self.set_contents(data.clone())?;
+        let clipboard = self.clipboard.clone();
+        let selection = S::atom(&clipboard.setter.atoms);
+        Ok(ClearHandle::spawn(ttl, move || {
+            let current = X11ClipboardContext::<S>::new().and_then(|mut reader| reader.get_contents());
+            if current.ok().as_ref() == Some(&data) {
+                disown(&clipboard.setter, &[selection]);
+            }
+        }))
+    }
+
+    // atoms are per server, so everything interned is resolved again
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.dual {
+            return Err("the selections of an X11DualContext share a connection, reconnect the X11DualContext instead".into());
+        }
+        let clipboard = connect()?;
+        self.property = self.property_on(&clipboard)?;
+        self.clipboard = Arc::new(clipboard);
+        Ok(())
+    }
+
+    fn image_preference(&self) -> &'static [&'static str] {
+        self.image_preference
+    }
+
+    // STRING is Latin-1 by convention, but the bytes are passed on as is
+    fn set_text_raw(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let target = self.clipboard.setter.atoms.string;
+        self.set_raw(target, data)
+    }
+
+    // owners that acquired the selection at CurrentTime report 0, which
+    // says nothing about when
+    fn last_change_time(&mut self) -> Result<Option<SystemTime>, Box<dyn Error + Send + Sync>> {
+        let getter = &self.clipboard.getter;
+        let owner = xcb::get_selection_owner(&getter.connection, S::atom(&getter.atoms))
+            .get_reply()
+            .map_err(x11_error)?
+            .owner();
+        if owner == xcb::NONE {
+            return Ok(None);
+        }
+        let timestamp = intern(getter, "TIMESTAMP")?;
+        // a single CARD32, laid out like an atom
+        let acquired = match parse_atoms(&self.load(timestamp, |_| ())?).first() {
+            Some(&0) | None => return Ok(None),
+            Some(&acquired) => acquired,
+        };
+        // server time wraps after about 49 days
+        let age = self.server_time()?.wrapping_sub(acquired);
+        Ok(SystemTime::now().checked_sub(Duration::from_millis(u64::from(age))))
+    }
+
+    // only a selection this context owns is given up, so clearing one that
+    // is already empty or belongs to another client is a no-op
+    fn clear(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let setter = &self.clipboard.setter;
+        disown(setter, &[S::atom(&setter.atoms)]);
+        Ok(())
+    }
+
+    fn clear_all(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let setter = &self.clipboard.setter;
+        disown(setter, &[setter.atoms.clipboard, setter.atoms.primary]);
+        Ok(())
     }
 
     //@TODO returns Ok even if target does not exist
-    fn get_target_contents(&mut self, clipboard_type: impl ToString) -> Result<Vec<u8>, Box<dyn Error>> {
-        Ok(self.0.load(
-            S::atom(&self.0.getter.atoms),
-            self.0.getter.get_atom(&clipboard_type.to_string())?,
-            self.0.getter.atoms.property,
-            Duration::from_secs(3),
-        )?)
-    }
-
-    fn set_target_contents(&mut self, clipboard_type: impl ToString, data: &[u8]) -> Result<(), Box<dyn Error>> {
-        Ok(self.0.store(
-            S::atom(&self.0.setter.atoms),
-            self.0.setter.get_atom(&clipboard_type.to_string())?,
+    fn get_target_contents(&mut self, clipboard_type: impl ToString) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.get_target_contents_with_progress(clipboard_type, |_| ())
+    }
+
+    // Absent targets are answered from TARGETS, which is cheaper than having
+    // the owner refuse the conversion. Owners that don't answer TARGETS are
+    // asked for the target regardless.
+    fn get_target_contents_with_progress(&mut self, target: impl ToString, progress: impl FnMut(usize)) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let target = formats::resolve_text_alias(&target.to_string()).to_owned();
+        let atom = self.clipboard.getter.get_atom(&target).map_err(x11_error)?;
+        if atom != self.clipboard.getter.atoms.targets {
+            let offered = self.offered_targets()?;
+            if !offered.is_empty() && !offered.contains(&atom) {
+                return Ok(Vec::new());
+            }
+        }
+        let data = self.load(atom, progress)?;
+        Ok(strip_text_bom(&target, data))
+    }
+
+    fn get_target_contents_opt(&mut self, target: impl ToString) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+        let target = formats::resolve_text_alias(&target.to_string()).to_owned();
+        let atom = self.clipboard.getter.get_atom(&target).map_err(x11_error)?;
+        if !self.offered_targets()?.contains(&atom) {
+            return Ok(None);
+        }
+        Ok(Some(strip_text_bom(&target, self.load(atom, |_| ())?)))
+    }
+
+    // TIMESTAMP, MULTIPLE and the other meta targets every owner offers are dropped
+    // only TARGETS is converted, which owners answer without rendering
+    fn peek_target(&mut self, target: impl ToString) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let target = intern(&self.clipboard.getter, formats::resolve_text_alias(&target.to_string()))?;
+        Ok(self.offered_targets()?.contains(&target))
+    }
+
+    fn list_data_targets(&mut self) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let getter = &self.clipboard.getter;
+        let meta = meta_targets(getter)?;
+        let mut names = Vec::new();
+        for target in self.offered_targets()? {
+            if meta.contains(&target) {
+                continue;
+            }
+            let reply = xcb::get_atom_name(&getter.connection, target).get_reply().map_err(x11_error)?;
+            let name = reply.name().to_owned();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    fn set_target_contents(&mut self, clipboard_type: impl ToString, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let clipboard_type = formats::resolve_text_alias(&clipboard_type.to_string()).to_owned();
+        check_text_encoding(&clipboard_type, data)?;
+        self.clipboard.store(
+            S::atom(&self.clipboard.setter.atoms),
+            intern(&self.clipboard.setter, &clipboard_type)?,
             data,
-        )?)
+        ).map_err(x11_error)
     }
 
-    fn set_multiple_targets(&mut self, targets: HashMap<impl ToString, &[u8]>) -> Result<(), Box<dyn Error>> {
-        let hash: Result<HashMap<_, _>, Box<dyn Error>> = targets.into_iter()
-            .map(|(key, value)| Ok((self.0.setter.get_atom(&key.to_string())?, value)))
+    fn set_multiple_targets(&mut self, targets: TargetMap<impl ToString>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if targets.is_empty() {
+            return self.clear();
+        }
+        let hash: Result<HashMap<_, _>, Box<dyn Error + Send + Sync>> = targets.into_iter()
+            .map(|(key, value)| {
+                let key = formats::resolve_text_alias(&key.to_string()).to_owned();
+                check_text_encoding(&key, value)?;
+                Ok((intern(&self.clipboard.setter, &key)?, value))
+            })
             .collect();
-        Ok(self.0.store_multiple(
-            S::atom(&self.0.setter.atoms),
+        self.clipboard.store_multiple(
+            S::atom(&self.clipboard.setter.atoms),
             hash?,
-        )?)
+        ).map_err(x11_error)
+    }
+}
+
+/// Handle to a selection served in the background, see
+/// `X11ClipboardContext::into_detached`
+pub struct DetachedSelection {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl DetachedSelection {
+    /// Close the connections, giving up the selection unless another
+    /// context of the process shares them
+    pub fn stop(self) {
+        let (lock, condvar) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        condvar.notify_one();
+        let _ = self.thread.join();
+    }
+}
+
+// gives up the selections the setter window owns, leaving those of other
+// clients alone
+fn disown(setter: &Context, selections: &[Atom]) {
+    for &selection in selections {
+        let owner = xcb::get_selection_owner(&setter.connection, selection).get_reply();
+        if owner.map(|reply| reply.owner() == setter.window).unwrap_or(false) {
+            xcb::set_selection_owner(&setter.connection, xcb::NONE, selection, xcb::CURRENT_TIME);
+        }
+    }
+    setter.connection.flush();
+}
+
+/// Context serving both the CLIPBOARD and the PRIMARY selection over one
+/// pair of X11 connections, e.g. for clipboard managers following both.
+pub struct X11DualContext {
+    clipboard: X11ClipboardContext<Clipboard>,
+    primary: X11ClipboardContext<Primary>,
+}
+
+impl X11DualContext {
+    pub fn new() -> Result<X11DualContext, Box<dyn Error + Send + Sync>> {
+        let shared = Arc::new(connect()?);
+        let mut clipboard = X11ClipboardContext::from_shared(shared.clone());
+        let mut primary = X11ClipboardContext::from_shared(shared);
+        clipboard.dual = true;
+        primary.dual = true;
+        Ok(X11DualContext { clipboard, primary })
+    }
+
+    /// Access the CLIPBOARD selection, as used by Ctrl-C and Ctrl-V
+    pub fn clipboard(&mut self) -> &mut X11ClipboardContext<Clipboard> {
+        &mut self.clipboard
+    }
+
+    /// Access the PRIMARY selection, as used by selecting and middle-clicking
+    pub fn primary(&mut self) -> &mut X11ClipboardContext<Primary> {
+        &mut self.primary
+    }
+
+    /// Copy every target offered on selection `F` to selection `T`, e.g.
+    /// `copy_selection::<Primary, Clipboard>()` makes a mouse selection
+    /// pasteable with Ctrl-V.
+    ///
+    /// All targets are read before anything is stored, so `T` changes owner
+    /// once. Targets the owner of `F` fails to convert are skipped, and `T` is
+    /// cleared when `F` offers nothing.
+    pub fn copy_selection<F: Selection, T: Selection>(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let ctx = &self.clipboard;
+        let getter = &ctx.clipboard.getter;
+        let load = |target| load_with_progress(
+            getter,
+            F::atom(&getter.atoms),
+            target,
+            ctx.property,
+            ctx.read_timeout,
+            ctx.max_read_bytes,
+            |_| (),
+        );
+        let meta = meta_targets(getter)?;
+        let mut contents = HashMap::new();
+        for target in parse_atoms(&load(getter.atoms.targets)?) {
+            if meta.contains(&target) || contents.contains_key(&target) {
+                continue;
+            }
+            if let Ok(data) = load(target) {
+                contents.insert(target, data);
+            }
+        }
+        if contents.is_empty() {
+            let setter = &ctx.clipboard.setter;
+            disown(setter, &[T::atom(&setter.atoms)]);
+            return Ok(());
+        }
+        ctx.clipboard.store_multiple(T::atom(&ctx.clipboard.setter.atoms), contents).map_err(x11_error)
+    }
+
+    /// Rebuild the shared connection, see `ClipboardProvider::reconnect`
+    ///
+    /// Both selections move to the new connection together; reconnecting
+    /// either one on its own fails.
+    pub fn reconnect(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let shared = Arc::new(connect()?);
+        // atoms are per server, and both are resolved before either half
+        // changes so a failure leaves the context as it was
+        let clipboard_property = self.clipboard.property_on(&shared)?;
+        let primary_property = self.primary.property_on(&shared)?;
+        self.clipboard.property = clipboard_property;
+        self.primary.property = primary_property;
+        self.clipboard.clipboard = shared.clone();
+        self.primary.clipboard = shared;
+        Ok(())
     }
 }